@@ -12,11 +12,27 @@ PARKOUR_API_SAVE_TIMER=1 PARKOUR_API_SECRET=your_secret_here cargo run
 #
 
 # Get the list of events
-curl http://localhost:3030/v1/events -H "authentication: your_secret_here"
+curl http://localhost:3030/v1/events
+
+# Get a single event
+curl http://localhost:3030/v1/events/:event_id
+
+# Get event statistics
+curl http://localhost:3030/v1/events/:event_id/stats
+
+# Get event overall ranking (with event scoring, or with another scoring method)
+curl http://localhost:3030/v1/events/:event_id/leaderboard
+curl "http://localhost:3030/v1/events/:event_id/leaderboard?scoring=points&points=10,6,3"
 
 # Create event
 curl -X POST http://localhost:3030/v1/events -H "authentication: your_secret_here" -H "Content-Type: application/json" --data @docs/example/body/event.json
 
+# Update event
+curl -X PATCH http://localhost:3030/v1/events/:event_id -H "authentication: your_secret_here" -H "Content-Type: application/json" --data '{"name": "New event name"}'
+
+# Delete event
+curl -X DELETE http://localhost:3030/v1/events/:event_id -H "authentication: your_secret_here"
+
 
 #
 #  ███╗   ███╗ █████╗ ██████╗ ███████╗
@@ -28,11 +44,20 @@ curl -X POST http://localhost:3030/v1/events -H "authentication: your_secret_her
 #
 
 # Get the list of maps
-curl http://localhost:3030/v1/events/:event_id/maps -H "authentication: your_secret_here"
+curl http://localhost:3030/v1/events/:event_id/maps
+
+# Get a single map
+curl http://localhost:3030/v1/maps/:map_id
 
 # Create map
 curl -X POST http://localhost:3030/v1/events/:event_id/maps -H "authentication: your_secret_here" -H "Content-Type: application/json" --data @docs/example/body/map.json
 
+# Update map
+curl -X PATCH http://localhost:3030/v1/events/:event_id/maps/:map_id -H "authentication: your_secret_here" -H "Content-Type: application/json" --data '{"map_name": "mp_rise"}'
+
+# Delete map
+curl -X DELETE http://localhost:3030/v1/events/:event_id/maps/:map_id -H "authentication: your_secret_here"
+
 
 #
 #  ██████╗  ██████╗ ██╗   ██╗████████╗███████╗███████╗
@@ -44,11 +69,20 @@ curl -X POST http://localhost:3030/v1/events/:event_id/maps -H "authentication:
 #
 
 # Get map routes
-curl http://localhost:3030/v1/maps/:map_id/routes -H "authentication: your_secret_here"
+curl http://localhost:3030/v1/maps/:map_id/routes
+
+# Get a single map route
+curl http://localhost:3030/v1/routes/:route_id
 
 # Create map route
 curl -X POST http://localhost:3030/v1/maps/:map_id/routes -H "authentication: your_secret_here" -H "Content-Type: application/json" --data @docs/example/body/route.json
 
+# Replace map route
+curl -X PUT http://localhost:3030/v1/maps/:map_id/routes/:route_id -H "authentication: your_secret_here" -H "Content-Type: application/json" --data @docs/example/body/route.json
+
+# Delete map route
+curl -X DELETE http://localhost:3030/v1/maps/:map_id/routes/:route_id -H "authentication: your_secret_here"
+
 
 #
 #  ███████╗ ██████╗ ██████╗ ██████╗ ███████╗███████╗
@@ -60,7 +94,81 @@ curl -X POST http://localhost:3030/v1/maps/:map_id/routes -H "authentication: yo
 #
 
 # Get the scores
-curl http://localhost:3030/v1/routes/:route_id/scores -H "authentication: your_secret_here"
+curl http://localhost:3030/v1/routes/:route_id/scores
+
+# Get the top ten scores
+curl "http://localhost:3030/v1/routes/:route_id/scores?limit=10"
+
+# Get a player's score, along with the 3 scores above and below it
+curl "http://localhost:3030/v1/routes/:route_id/scores?around=:player_id&limit=3"
 
 # Submit a new score
 curl -X POST http://localhost:3030/v1/routes/:route_id/scores -H "authentication: your_secret_here" -H "Content-Type: application/json" --data @docs/example/body/score.json
+
+# Submit a new score with checkpoint splits (one per route checkpoint)
+curl -X POST http://localhost:3030/v1/routes/:route_id/scores -H "authentication: your_secret_here" -H "Content-Type: application/json" --data @docs/example/body/score_with_splits.json
+
+# Update a player's score
+curl -X PATCH http://localhost:3030/v1/routes/:route_id/scores/:player_id -H "authentication: your_secret_here" -H "Content-Type: application/json" --data '{"time": 42.5}'
+
+# Delete a player's score
+curl -X DELETE http://localhost:3030/v1/routes/:route_id/scores/:player_id -H "authentication: your_secret_here"
+
+# Get the runs of a player on a route
+curl http://localhost:3030/v1/routes/:route_id/players/:player_id/runs
+
+# Get a player profile (name history, and results on all events)
+curl http://localhost:3030/v1/players/:player_id
+
+
+#
+#  ██╗  ██╗███████╗██╗   ██╗███████╗
+#  ██║ ██╔╝██╔════╝╚██╗ ██╔╝██╔════╝
+#  █████╔╝ █████╗   ╚████╔╝ ███████╗
+#  ██╔═██╗ ██╔══╝    ╚██╔╝  ╚════██║
+#  ██║  ██╗███████╗   ██║   ███████║
+#  ╚═╝  ╚═╝╚══════╝   ╚═╝   ╚══════╝
+#
+
+# Get the list of keys
+curl http://localhost:3030/v1/keys -H "authentication: your_secret_here"
+
+# Issue a key
+curl -X POST http://localhost:3030/v1/keys -H "authentication: your_secret_here" -H "Content-Type: application/json" --data '{"name": "EU community server", "scopes": ["scores:write"]}'
+
+# Issue a key that can only submit signed scores
+curl -X POST http://localhost:3030/v1/keys -H "authentication: your_secret_here" -H "Content-Type: application/json" --data '{"name": "NA community server", "scopes": ["scores:write"], "signed": true}'
+
+# Revoke a key
+curl -X DELETE http://localhost:3030/v1/keys/:key_id -H "authentication: your_secret_here"
+
+
+#
+#  ███╗   ███╗ ██████╗ ██████╗ ███████╗██████╗  █████╗ ████████╗██╗ ██████╗ ███╗   ██╗
+#  ████╗ ████║██╔═══██╗██╔══██╗██╔════╝██╔══██╗██╔══██╗╚══██╔══╝██║██╔═══██╗████╗  ██║
+#  ██╔████╔██║██║   ██║██║  ██║█████╗  ██████╔╝███████║   ██║   ██║██║   ██║██╔██╗ ██║
+#  ██║╚██╔╝██║██║   ██║██║  ██║██╔══╝  ██╔══██╗██╔══██║   ██║   ██║██║   ██║██║╚██╗██║
+#  ██║ ╚═╝ ██║╚██████╔╝██████╔╝███████╗██║  ██║██║  ██║   ██║   ██║╚██████╔╝██║ ╚████║
+#  ╚═╝     ╚═╝ ╚═════╝ ╚═════╝ ╚══════╝╚═╝  ╚═╝╚═╝  ╚═╝   ╚═╝   ╚═╝ ╚═════╝ ╚═╝  ╚═══╝
+#
+
+# Set a route minimum plausible time (in seconds)
+curl -X PATCH http://localhost:3030/v1/maps/:map_id/routes/:route_id -H "authentication: your_secret_here" -H "Content-Type: application/json" --data '{"min_time": 20}'
+
+# Get the runs awaiting moderation
+curl http://localhost:3030/v1/moderation/runs -H "authentication: your_secret_here"
+
+# Approve a run
+curl -X POST http://localhost:3030/v1/moderation/runs/:run_id/approve -H "authentication: your_secret_here"
+
+# Reject a run
+curl -X POST http://localhost:3030/v1/moderation/runs/:run_id/reject -H "authentication: your_secret_here"
+
+# Subscribe a webhook to new records (Discord message format)
+curl -X POST http://localhost:3030/v1/webhooks -H "authentication: your_secret_here" -H "Content-Type: application/json" --data '{"url": "http://discord-relay:8080/api/webhooks/123/abc", "format": "discord"}'
+
+# Get the deliveries of a webhook
+curl http://localhost:3030/v1/webhooks/:webhook_id/deliveries -H "authentication: your_secret_here"
+
+# Receive live notifications of an event (Server-Sent Events)
+curl -N "http://localhost:3030/v1/notifications/sse?event_id=:event_id"
@@ -1,5 +1,11 @@
 function initDocument() {
+    initUpcoming();
+
     let tables = document.querySelectorAll('.result_scores');
+    if (tables.length === 0) {
+        // No event, or event without routes
+        return;
+    }
     let selected_table = tables[0];
     let noTableFound = true;
 
@@ -22,11 +28,15 @@ function initDocument() {
     }
 
     // Select the table which has the biggest number of scores to be displayed
+    // (overall ranking is only displayed when requested)
     if (noTableFound) {
         let count = 0;
 
         for (let i=0; i<tables.length; i++) {
             const table = tables[i];
+            if (table.id === 'result_overall') {
+                continue;
+            }
             const body = table.querySelector('tbody');
             const childrenCount = body.children.length;
 
@@ -44,7 +54,9 @@ function initDocument() {
     // Set up route names in the route selector
     const routeItems = document.querySelectorAll('#resultsList li');
     for (const item of routeItems) {
-        item.innerText = `${item.dataset.routeName} (${getMapName(item.dataset.mapName, true)})`;
+        item.innerText = item.dataset.mapName
+            ? `${item.dataset.routeName} (${getMapName(item.dataset.mapName, true)})`
+            : item.dataset.routeName;
     }
 }
 
@@ -59,6 +71,14 @@ function displayTable(tableId, routeName, mapName) {
     let table = document.querySelector('#' + tableId);
     table.setAttribute('show', '');
 
+    // Overall ranking spans all maps
+    if (!mapName) {
+        document.getElementById('routeName').innerText = routeName;
+        document.getElementById('mapName').innerText = '[All routes]';
+        document.getElementById('routeSelectorImage').setAttribute('src', '/assets/img/maps/unknown.webp');
+        return;
+    }
+
     // Handle unknown maps
     let finalMapName = getMapName(mapName);
     const unknownMap = finalMapName === 'Unknown';
@@ -66,7 +86,37 @@ function displayTable(tableId, routeName, mapName) {
     // Update map card
     document.getElementById('routeName').innerText = routeName;
     document.getElementById('mapName').innerText = '[' + (unknownMap ? mapName : finalMapName) + ']';
-    document.getElementById('routeSelectorImage').setAttribute('src', 'assets/img/maps/' + (unknownMap ? 'unknown' : mapName) + '.webp')
+    document.getElementById('routeSelectorImage').setAttribute('src', '/assets/img/maps/' + (unknownMap ? 'unknown' : mapName) + '.webp')
+}
+
+function initUpcoming() {
+    const countdown = document.getElementById('countdown');
+    if (countdown === null) {
+        return;
+    }
+
+    // Set up route names in the upcoming routes list
+    const routeItems = document.querySelectorAll('#upcomingRoutes li');
+    for (const item of routeItems) {
+        item.innerText = `${item.dataset.routeName} (${getMapName(item.dataset.mapName, true)})`;
+    }
+
+    const start = parseInt(countdown.dataset.start, 10) * 1000;
+    const update = () => {
+        const remaining = Math.max(0, Math.floor((start - Date.now()) / 1000));
+        if (remaining === 0) {
+            // Event has started, display its scoreboard
+            window.location.reload();
+            return;
+        }
+        const days = Math.floor(remaining / 86400);
+        const hours = Math.floor(remaining % 86400 / 3600);
+        const minutes = Math.floor(remaining % 3600 / 60);
+        const seconds = remaining % 60;
+        countdown.innerText = `in ${days}d ${hours}h ${minutes}m ${seconds}s`;
+        setTimeout(update, 1000);
+    };
+    update();
 }
 
 function getMapName(map, returnOriginalNameIfUnknown = false) {
@@ -0,0 +1,245 @@
+use std::env;
+use std::fs::read_to_string;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::log;
+
+const USAGE: &str = "Usage: parkour-api [OPTIONS]
+
+Options:
+    --config <PATH>           TOML configuration file
+    --data-dir <PATH>         directory where state is stored (default: data)
+    --scoreboard-dir <PATH>   directory holding scoreboard templates and assets (default: scoreboard)
+    --template-reload <SECONDS>
+                              delay between two checks for scoreboard template changes, 0 for no reload (default: 0)
+    --address <IP>            address to listen on (default: 0.0.0.0)
+    --port <PORT>             port to listen on (default: 3030)
+    --save-timer <MINUTES>    duration between two state saves (default: 15)
+    --storage <BACKEND>       storage backend, json or sqlite (default: json)
+    --integrity <MODE>        integrity issues handling, report or repair (default: report)
+    --rate-limit <REQUESTS>   requests per minute allowed to anonymous clients, 0 for no limit (default: 0)
+    --help                    print this message";
+
+
+/// API configuration.
+/// 
+/// Each setting can be provided (by decreasing priority) as a command-line
+/// flag, as an environment variable, or in a TOML configuration file.
+/// 
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub scoreboard_dir: PathBuf,
+    pub address: SocketAddr,
+    pub save_timer_minutes: u64,
+    pub storage: String,
+    pub repair_integrity: bool,
+    pub rate_limit: u32,
+    pub template_reload_seconds: u64
+}
+
+/// Raw settings, as found in one configuration source.
+/// 
+#[derive(Debug, Default)]
+struct Settings {
+    data_dir: Option<String>,
+    scoreboard_dir: Option<String>,
+    address: Option<String>,
+    port: Option<String>,
+    save_timer: Option<String>,
+    storage: Option<String>,
+    integrity: Option<String>,
+    rate_limit: Option<String>,
+    template_reload: Option<String>
+}
+
+impl Settings {
+    /// Fills unset settings with those of a lower-priority source.
+    /// 
+    fn or(self, other: Settings) -> Settings {
+        Settings {
+            data_dir: self.data_dir.or(other.data_dir),
+            scoreboard_dir: self.scoreboard_dir.or(other.scoreboard_dir),
+            address: self.address.or(other.address),
+            port: self.port.or(other.port),
+            save_timer: self.save_timer.or(other.save_timer),
+            storage: self.storage.or(other.storage),
+            integrity: self.integrity.or(other.integrity),
+            rate_limit: self.rate_limit.or(other.rate_limit),
+            template_reload: self.template_reload.or(other.template_reload)
+        }
+    }
+}
+
+/// Configuration file settings; unknown keys are rejected so that typos
+/// don't go unnoticed.
+/// 
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileSettings {
+    data_dir: Option<String>,
+    scoreboard_dir: Option<String>,
+    address: Option<String>,
+    port: Option<u16>,
+    save_timer: Option<u64>,
+    storage: Option<String>,
+    integrity: Option<String>,
+    rate_limit: Option<u32>,
+    template_reload: Option<u64>
+}
+
+
+/// Builds configuration from command-line arguments, environment variables
+/// and configuration file; invalid configuration stops the API.
+/// 
+pub fn load() -> Config {
+    match try_load() {
+        Ok(config) => config,
+        Err(err) => {
+            log::error(&format!("Invalid configuration: {}.", err));
+            std::process::exit(1);
+        }
+    }
+}
+
+
+fn try_load() -> Result<Config, String> {
+    let (flags, config_file) = parse_args(env::args().skip(1))?;
+    let config_file = config_file.or_else(|| env::var("PARKOUR_API_CONFIG").ok());
+
+    let settings = flags
+        .or(from_env())
+        .or(match config_file {
+            Some(path) => from_file(&path)?,
+            None => Settings::default()
+        });
+
+    validate(settings)
+}
+
+
+/// Parses command-line flags, returning them along with the configuration
+/// file path, if any.
+/// 
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Settings, Option<String>), String> {
+    let mut settings = Settings::default();
+    let mut config_file = None;
+
+    while let Some(flag) = args.next() {
+        if flag == "--help" || flag == "-h" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        let value = args.next().ok_or(format!("missing value for \"{}\" flag", flag))?;
+        match flag.as_str() {
+            "--config" => config_file = Some(value),
+            "--data-dir" => settings.data_dir = Some(value),
+            "--scoreboard-dir" => settings.scoreboard_dir = Some(value),
+            "--address" => settings.address = Some(value),
+            "--port" => settings.port = Some(value),
+            "--save-timer" => settings.save_timer = Some(value),
+            "--storage" => settings.storage = Some(value),
+            "--integrity" => settings.integrity = Some(value),
+            "--rate-limit" => settings.rate_limit = Some(value),
+            "--template-reload" => settings.template_reload = Some(value),
+            _ => return Err(format!("unknown \"{}\" flag (see --help)", flag))
+        }
+    }
+
+    Ok((settings, config_file))
+}
+
+
+fn from_env() -> Settings {
+    Settings {
+        data_dir: env::var("PARKOUR_API_DATA_DIR").ok(),
+        scoreboard_dir: env::var("PARKOUR_API_SCOREBOARD_DIR").ok(),
+        address: env::var("PARKOUR_API_ADDRESS").ok(),
+        port: env::var("PARKOUR_API_PORT").ok(),
+        save_timer: env::var("PARKOUR_API_SAVE_TIMER").ok(),
+        storage: env::var("PARKOUR_API_STORAGE").ok(),
+        integrity: env::var("PARKOUR_API_INTEGRITY").ok(),
+        rate_limit: env::var("PARKOUR_API_RATE_LIMIT").ok(),
+        template_reload: env::var("PARKOUR_API_TEMPLATE_RELOAD").ok()
+    }
+}
+
+
+fn from_file(path: &str) -> Result<Settings, String> {
+    let data = read_to_string(path)
+        .map_err(|err| format!("failed reading \"{}\" configuration file [{}]", path, err))?;
+    let file: FileSettings = toml::from_str(&data)
+        .map_err(|err| format!("failed parsing \"{}\" configuration file [{}]", path, err))?;
+    log::info(&format!("Loaded configuration from \"{}\" file.", path));
+
+    Ok(Settings {
+        data_dir: file.data_dir,
+        scoreboard_dir: file.scoreboard_dir,
+        address: file.address,
+        port: file.port.map(|p| p.to_string()),
+        save_timer: file.save_timer.map(|t| t.to_string()),
+        storage: file.storage,
+        integrity: file.integrity,
+        rate_limit: file.rate_limit.map(|l| l.to_string()),
+        template_reload: file.template_reload.map(|t| t.to_string())
+    })
+}
+
+
+fn validate(settings: Settings) -> Result<Config, String> {
+    let data_dir = PathBuf::from(settings.data_dir.unwrap_or(String::from("data")));
+    if data_dir.exists() && !data_dir.is_dir() {
+        return Err(format!("data directory \"{}\" is not a directory", data_dir.display()));
+    }
+
+    // A missing scoreboard directory only disables the scoreboard
+    let scoreboard_dir = PathBuf::from(settings.scoreboard_dir.unwrap_or(String::from("scoreboard")));
+
+    let ip = settings.address.unwrap_or(String::from("0.0.0.0"));
+    let ip: IpAddr = ip.parse()
+        .map_err(|_| format!("\"{}\" is not a valid IP address", ip))?;
+    let port = settings.port.unwrap_or(String::from("3030"));
+    let port: u16 = match port.parse() {
+        Ok(port) if port > 0 => port,
+        _ => return Err(format!("\"{}\" is not a valid port number", port))
+    };
+
+    let save_timer = settings.save_timer.unwrap_or(String::from("15"));
+    let save_timer_minutes: u64 = match save_timer.parse() {
+        Ok(minutes) if minutes > 0 => minutes,
+        _ => return Err(format!("save timer must be a positive number of minutes (got \"{}\")", save_timer))
+    };
+
+    let storage = settings.storage.unwrap_or(String::from("json"));
+    if storage != "json" && storage != "sqlite" {
+        return Err(format!("unknown \"{}\" storage backend (expected \"json\" or \"sqlite\")", storage));
+    }
+
+    let repair_integrity = match settings.integrity.as_deref() {
+        None | Some("report") => false,
+        Some("repair") => true,
+        Some(mode) => return Err(format!("unknown \"{}\" integrity mode (expected \"report\" or \"repair\")", mode))
+    };
+
+    let rate_limit = settings.rate_limit.unwrap_or(String::from("0"));
+    let rate_limit: u32 = rate_limit.parse()
+        .map_err(|_| format!("rate limit must be a number of requests per minute (got \"{}\")", rate_limit))?;
+
+    let template_reload = settings.template_reload.unwrap_or(String::from("0"));
+    let template_reload_seconds: u64 = template_reload.parse()
+        .map_err(|_| format!("template reload must be a number of seconds (got \"{}\")", template_reload))?;
+
+    Ok(Config {
+        data_dir,
+        scoreboard_dir,
+        address: SocketAddr::new(ip, port),
+        save_timer_minutes,
+        storage,
+        repair_integrity,
+        rate_limit,
+        template_reload_seconds
+    })
+}
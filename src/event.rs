@@ -1,20 +1,33 @@
 use serde::{Serialize, Deserialize};
+use serde_json::json;
 use uuid::Uuid;
-use warp::{http, Filter, Reply, Rejection};
+use warp::{hyper::StatusCode, Filter, Reply, Rejection};
 
-use crate::Store;
+use crate::{Store, storage, keys::{self, Scope}, journal::Mutation, leaderboard::Scoring, map};
 
 pub type Events = Vec<Event>;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Event {
-    name: String,
-    description: String,
+    pub name: String,
+    pub description: String,
     pub start: i64,
     pub end: i64,
-    pub id: Option<String>
+    pub id: Option<String>,
+    /// How the overall ranking of the event is computed (defaults to the sum
+    /// of route ranks)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scoring: Option<Scoring>
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EventPatch {
+    name: Option<String>,
+    description: Option<String>,
+    start: Option<i64>,
+    end: Option<i64>,
+    scoring: Option<Scoring>
+}
 
 /// Returns the list of all events.
 /// 
@@ -26,6 +39,26 @@ async fn get_list(
 }
 
 
+/// Returns a single event, based on its identifier.
+/// 
+async fn get_event(
+    event_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let read_lock = store.events_list.read();
+        match read_lock.iter().find(|e| e.id.as_deref() == Some(event_id.as_str())) {
+            Some(event) => Ok(warp::reply::with_status(
+                warp::reply::json(event),
+                StatusCode::OK,
+            )),
+            None => Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+}
+
+
 /// This middleware creates `Event` payloads from POST request bodies.
 /// 
 pub fn post_json() -> impl Filter<Extract = (Event,), Error = Rejection> + Clone {
@@ -39,36 +72,167 @@ async fn create_event(
     entry: Event,
     store: Store
     ) -> Result<impl Reply, Rejection> {
+        if entry.start > entry.end {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event cannot end before it starts."})),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
         // Checking for existing event
-        let events: Vec<Event> = store.events_list.read().to_vec();
-        let index = events.iter().position(|e| e.name == entry.name).unwrap_or(usize::MAX);
-        if index != usize::MAX {
+        let mut write_lock = store.events_list.write();
+        if write_lock.iter().any(|e| e.name == entry.name) {
             return Ok(warp::reply::with_status(
-                "",
-                http::StatusCode::ALREADY_REPORTED,
+                warp::reply::json(&json!({"error": "Event already exists."})),
+                StatusCode::ALREADY_REPORTED,
             ));
         }
 
         let event_id = Uuid::new_v4().to_string();
-        let mut write_lock = store.events_list.write();
-        write_lock.push(Event { name: entry.name, description: entry.description, start: entry.start, end: entry.end, id: Some(event_id.clone()) });
+        let event = Event { name: entry.name, description: entry.description, start: entry.start, end: entry.end, id: Some(event_id.clone()), scoring: entry.scoring };
+        write_lock.push(event.clone());
 
         // Create associated maps
         let mut maps_write_lock = store.maps_list.write();
         maps_write_lock.insert(event_id, [].to_vec());
+        store.storage.record(Mutation::PutEvent { event });
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Event successfully created."})),
+            StatusCode::CREATED,
+        ))
+}
+
+
+/// This middleware creates `EventPatch` payloads from PATCH request bodies.
+/// 
+pub fn patch_json() -> impl Filter<Extract = (EventPatch,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+
+/// Replaces all fields of an existing event; optional fields absent from the
+/// body are cleared.
+/// 
+async fn replace_event(
+    event_id: String,
+    entry: Event,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut write_lock = store.events_list.write();
+        let index = write_lock.iter().position(|e| e.id.as_deref() == Some(event_id.as_str())).unwrap_or(usize::MAX);
+        if index == usize::MAX {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+
+        Ok(store_event(&store, &mut write_lock, index, entry))
+}
+
+
+/// Updates fields of an existing event; fields absent from the patch are
+/// left untouched.
+/// 
+async fn update_event(
+    event_id: String,
+    patch: EventPatch,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut write_lock = store.events_list.write();
+        let index = write_lock.iter().position(|e| e.id.as_deref() == Some(event_id.as_str())).unwrap_or(usize::MAX);
+        if index == usize::MAX {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+
+        let current = write_lock[index].clone();
+        let event = Event {
+            name: patch.name.unwrap_or(current.name),
+            description: patch.description.unwrap_or(current.description),
+            start: patch.start.unwrap_or(current.start),
+            end: patch.end.unwrap_or(current.end),
+            id: current.id,
+            scoring: patch.scoring.or(current.scoring)
+        };
+        Ok(store_event(&store, &mut write_lock, index, event))
+}
+
+
+/// Stores `event` in place of the event at `index` (keeping its id), after
+/// checking it doesn't conflict with other events.
+/// 
+fn store_event(
+    store: &Store,
+    events: &mut Events,
+    index: usize,
+    mut event: Event
+    ) -> warp::reply::WithStatus<warp::reply::Json> {
+        // Checking for name conflicts with other events
+        if events.iter().enumerate().any(|(i, e)| i != index && e.name == event.name) {
+            return warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event name already used."})),
+                StatusCode::ALREADY_REPORTED,
+            );
+        }
+
+        // Checking that the updated event doesn't end before it starts
+        if event.start > event.end {
+            return warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event cannot end before it starts."})),
+                StatusCode::BAD_REQUEST,
+            );
+        }
+
+        event.id = events[index].id.clone();
+        events[index] = event.clone();
+        store.storage.record(Mutation::PutEvent { event });
+
+        warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Event successfully updated."})),
+            StatusCode::OK,
+        )
+}
+
+
+/// Deletes an event, as well as all its maps, their routes and the routes' scores.
+/// 
+async fn delete_event(
+    event_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut write_lock = store.events_list.write();
+        let index = write_lock.iter().position(|e| e.id.as_deref() == Some(event_id.as_str())).unwrap_or(usize::MAX);
+        if index == usize::MAX {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+        write_lock.remove(index);
+        map::remove_event_maps(&store, &event_id);
+        store.storage.record(Mutation::DeleteEvent { event_id });
 
         Ok(warp::reply::with_status(
-            "",
-            http::StatusCode::CREATED,
+            warp::reply::json(&json!({"message": "Event successfully deleted."})),
+            StatusCode::OK,
         ))
 }
 
 
 /// Returns all event-associated routes:
 ///     * one route to list all events;
-///     * one route to create events.
+///     * one route to get an event;
+///     * one route to create events;
+///     * one route to replace an event;
+///     * one route to partially update an event;
+///     * one route to delete an event.
 /// 
 pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let write_filter = keys::authorize(store.clone(), Scope::RoutesWrite);
     let store_filter = warp::any().map(move || store.clone());
 
     let get_all_events = warp::get()
@@ -78,13 +242,64 @@ pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error =
         .and(store_filter.clone())
         .and_then(get_list);
 
+    let get_event_route = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(get_event);
+
     let event_creation_route = warp::post()
         .and(warp::path("v1"))
         .and(warp::path("events"))
         .and(warp::path::end())
+        .and(write_filter.clone())
         .and(post_json())
+        .and(store_filter.clone())
+        .and_then(create_event)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let event_replacement_route = warp::put()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(post_json())
+        .and(store_filter.clone())
+        .and_then(replace_event)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let event_update_route = warp::patch()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(patch_json())
+        .and(store_filter.clone())
+        .and_then(update_event)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let event_deletion_route = warp::delete()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(store_filter.clone())
+        .and_then(delete_event)
         .and(store_filter)
-        .and_then(create_event);
+        .and_then(storage::wait_commit);
 
-    get_all_events.or(event_creation_route)
+    get_all_events
+        .or(get_event_route)
+        .or(event_creation_route)
+        .or(event_replacement_route)
+        .or(event_update_route)
+        .or(event_deletion_route)
 }
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+
+use crate::journal::Mutation;
+use crate::{Store, log, map, route};
+
+
+/// A broken link between store collections.
+/// 
+enum Issue {
+    /// Maps are listed under an event that does not exist
+    OrphanMaps { event_id: String },
+    /// Routes are listed under a map that does not exist
+    OrphanRoutes { map_id: String },
+    /// Scores are listed under a route that does not exist
+    OrphanScores { route_id: String },
+    /// Runs are listed under a route that does not exist
+    OrphanRuns { route_id: String },
+    /// Runs awaiting moderation belong to a route that does not exist
+    OrphanFlaggedRuns { route_id: String },
+    /// An event has no maps list
+    MissingMaps { event_id: String },
+    /// A map has no routes list
+    MissingRoutes { map_id: String },
+    /// A route has no scores list
+    MissingScores { route_id: String }
+}
+
+impl Issue {
+    fn describe(&self) -> String {
+        match self {
+            Issue::OrphanMaps { event_id } => format!("maps are associated to unknown event {}", event_id),
+            Issue::OrphanRoutes { map_id } => format!("routes are associated to unknown map {}", map_id),
+            Issue::OrphanScores { route_id } => format!("scores are associated to unknown route {}", route_id),
+            Issue::OrphanRuns { route_id } => format!("runs are associated to unknown route {}", route_id),
+            Issue::OrphanFlaggedRuns { route_id } => format!("flagged runs are associated to unknown route {}", route_id),
+            Issue::MissingMaps { event_id } => format!("event {} has no maps list", event_id),
+            Issue::MissingRoutes { map_id } => format!("map {} has no routes list", map_id),
+            Issue::MissingScores { route_id } => format!("route {} has no scores list", route_id)
+        }
+    }
+
+    /// Fixes the issue in the store; orphaned entities are deleted (along with
+    /// entities depending on them), and missing lists are created empty.
+    /// 
+    /// Orphans are listed under an identifier that has no parent entity to
+    /// delete, hence their removal being recorded as such.
+    /// 
+    fn repair(self, store: &Store) {
+        match self {
+            Issue::OrphanMaps { event_id } => {
+                map::remove_event_maps(store, &event_id);
+                store.storage.record(Mutation::DeleteEvent { event_id });
+            },
+            Issue::OrphanRoutes { map_id } => {
+                route::remove_map_routes(store, &map_id);
+                store.storage.record(Mutation::RemoveOrphanRoutes { map_id });
+            },
+            Issue::OrphanScores { route_id } | Issue::OrphanRuns { route_id } | Issue::OrphanFlaggedRuns { route_id } => {
+                route::remove_route_scores(store, &route_id);
+                store.storage.record(Mutation::RemoveOrphanScores { route_id });
+            },
+            // Recording the entity again recreates its missing list on replay
+            Issue::MissingMaps { event_id } => {
+                store.maps_list.write().insert(event_id.clone(), Vec::new());
+                let event = store.events_list.read().iter().find(|e| e.id.as_deref() == Some(event_id.as_str())).cloned();
+                if let Some(event) = event {
+                    store.storage.record(Mutation::PutEvent { event });
+                }
+            },
+            Issue::MissingRoutes { map_id } => {
+                store.routes_list.write().insert(map_id.clone(), Vec::new());
+                if let Some((event_id, map)) = map::find_map(store, &map_id) {
+                    store.storage.record(Mutation::PutMap { event_id, map });
+                }
+            },
+            Issue::MissingScores { route_id } => {
+                store.scores_list.write().insert(route_id.clone(), Vec::new());
+                if let Some((map_id, route)) = route::find_route(store, &route_id) {
+                    store.storage.record(Mutation::PutRoute { map_id, route: Box::new(route) });
+                }
+            }
+        }
+    }
+}
+
+
+/// Lists referential integrity issues: every maps list must belong to an
+/// existing event, every routes list to an existing map, and every scores
+/// list to an existing route (and conversely); run histories and runs awaiting
+/// moderation must belong to an existing route too.
+/// 
+fn find_issues(store: &Store) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+
+    let event_ids: HashSet<String> = store.events_list.read().iter().filter_map(|e| e.id.clone()).collect();
+    let maps_list = store.maps_list.read();
+    let routes_list = store.routes_list.read();
+    let scores_list = store.scores_list.read();
+    let runs_list = store.runs_list.read();
+    let flagged_runs = store.flagged_runs.read();
+
+    // Only entities that are reachable from an event are considered existing
+    let mut map_ids: HashSet<String> = HashSet::new();
+    for (event_id, maps) in maps_list.iter() {
+        if !event_ids.contains(event_id) {
+            issues.push(Issue::OrphanMaps { event_id: event_id.clone() });
+            continue;
+        }
+        map_ids.extend(maps.iter().filter_map(|m| m.id.clone()));
+    }
+    let mut route_ids: HashSet<String> = HashSet::new();
+    for (map_id, routes) in routes_list.iter() {
+        if !map_ids.contains(map_id) {
+            issues.push(Issue::OrphanRoutes { map_id: map_id.clone() });
+            continue;
+        }
+        route_ids.extend(routes.iter().filter_map(|r| r.id.clone()));
+    }
+    for route_id in scores_list.keys() {
+        if !route_ids.contains(route_id) {
+            issues.push(Issue::OrphanScores { route_id: route_id.clone() });
+        }
+    }
+    // Runs of routes whose scores are orphaned are repaired along with them
+    for route_id in runs_list.keys() {
+        if !route_ids.contains(route_id) && !scores_list.contains_key(route_id) {
+            issues.push(Issue::OrphanRuns { route_id: route_id.clone() });
+        }
+    }
+    let mut flagged_route_ids: HashSet<&String> = HashSet::new();
+    for run in flagged_runs.iter() {
+        let route_id = &run.route_id;
+        if !route_ids.contains(route_id) && !scores_list.contains_key(route_id) && !runs_list.contains_key(route_id)
+            && flagged_route_ids.insert(route_id) {
+            issues.push(Issue::OrphanFlaggedRuns { route_id: route_id.clone() });
+        }
+    }
+
+    for event_id in event_ids.iter().filter(|id| !maps_list.contains_key(*id)) {
+        issues.push(Issue::MissingMaps { event_id: event_id.clone() });
+    }
+    for map_id in map_ids.iter().filter(|id| !routes_list.contains_key(*id)) {
+        issues.push(Issue::MissingRoutes { map_id: map_id.clone() });
+    }
+    for route_id in route_ids.iter().filter(|id| !scores_list.contains_key(*id)) {
+        issues.push(Issue::MissingScores { route_id: route_id.clone() });
+    }
+
+    issues
+}
+
+
+/// Checks the integrity of loaded state.
+/// 
+/// By default, the API refuses to start if issues are found; with `repair`
+/// enabled (`integrity = "repair"` setting), they're fixed instead (orphaned
+/// entities are deleted).
+/// 
+pub fn check(store: &Store, repair: bool) {
+    let issues = find_issues(store);
+    if issues.is_empty() {
+        log::info("State integrity check passed.");
+        return;
+    }
+
+    for issue in &issues {
+        log::warn(&format!("Integrity issue: {}.", issue.describe()));
+    }
+    if !repair {
+        log::error(&format!("Found {} integrity issue(s); restart with integrity mode set to \"repair\" to fix them.", issues.len()));
+        std::process::exit(2);
+    }
+
+    let count = issues.len();
+    for issue in issues {
+        issue.repair(store);
+    }
+    log::info(&format!("Repaired {} integrity issue(s).", count));
+}
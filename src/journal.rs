@@ -0,0 +1,332 @@
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::{Serialize, Deserialize};
+
+use crate::event::Event;
+use crate::map::{self, Map};
+use crate::route::{self, MapRoute};
+use crate::moderation::FlaggedRun;
+use crate::players::{self, Player};
+use crate::runs::{self, Run};
+use crate::scores::{self, ScoreEntry};
+use crate::storage::{Commit, WriteQueue};
+use crate::{Store, log, persistence};
+
+
+/// A change made to the store.
+/// 
+/// Mutations carry the resulting state of the entity they touch (rather than
+/// the request that led to it), so that replaying one on top of a state that
+/// already includes it leaves that state unchanged.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Mutation {
+    PutEvent { event: Event },
+    DeleteEvent { event_id: String },
+    PutMap { event_id: String, map: Map },
+    DeleteMap { event_id: String, map_id: String },
+    PutRoute { map_id: String, route: Box<MapRoute> },
+    DeleteRoute { map_id: String, route_id: String },
+    PutScore { route_id: String, player: String, entry: ScoreEntry },
+    DeleteScore { route_id: String, player: String },
+    AddRun { route_id: String, run: Run },
+    PutPlayer { player: Player },
+    FlagRun { run: FlaggedRun },
+    UnflagRun { run_id: String },
+    /// Removal of routes listed under a map that does not exist
+    RemoveOrphanRoutes { map_id: String },
+    /// Removal of scores, runs and flagged runs listed under a route that does
+    /// not exist
+    RemoveOrphanScores { route_id: String }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct JournalEntry {
+    seq: u64,
+    #[serde(flatten)]
+    mutation: Mutation
+}
+
+struct JournalState {
+    path: Option<PathBuf>,
+    file: Option<File>
+}
+
+
+/// Append-only log of the mutations applied to the store since the last
+/// snapshot, used to restore them if the API stops before next save.
+/// 
+/// Mutations are appended by a writer thread, which syncs all entries queued
+/// while it was writing the previous ones at once.
+/// 
+pub struct Journal {
+    state: Arc<Mutex<JournalState>>,
+    queue: WriteQueue<Mutation>
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(JournalState { path: None, file: None }));
+        let writer_state = state.clone();
+        Journal {
+            state,
+            queue: WriteQueue::start(move |batch| write_entries(&writer_state, batch))
+        }
+    }
+
+    /// Sequence number of the last recorded mutation.
+    /// 
+    pub fn last_seq(&self) -> u64 {
+        self.queue.last_seq()
+    }
+
+    /// Starts journaling to the given file, rewriting it so it only contains
+    /// the input entries (this drops entries covered by the loaded snapshot, as
+    /// well as a possibly truncated last line).
+    /// 
+    fn open(&self, path: &Path, entries: &[JournalEntry], last_seq: u64) -> Result<(), String> {
+        let mut state = self.state.lock();
+        persistence::replace_file(path, &serialize_entries(entries)?)?;
+        state.file = Some(open_append(path)?);
+        state.path = Some(path.to_path_buf());
+        self.queue.reset(last_seq);
+        Ok(())
+    }
+
+    /// Records a mutation; it is written to disk in the background.
+    /// 
+    pub fn append(&self, mutation: Mutation) {
+        self.queue.push(mutation);
+    }
+
+    /// Returns a handle on the writing of all mutations recorded so far.
+    /// 
+    pub fn commit(&self) -> Commit {
+        self.queue.commit()
+    }
+
+    /// Drops all entries that are included in a snapshot.
+    /// 
+    pub fn compact(&self, snapshot_seq: u64) -> Result<(), String> {
+        let mut state = self.state.lock();
+        let path = match state.path.clone() {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        let entries: Vec<JournalEntry> = read_entries(&path)?
+            .into_iter()
+            .filter(|e| e.seq > snapshot_seq)
+            .collect();
+        persistence::replace_file(&path, &serialize_entries(&entries)?)?;
+        state.file = Some(open_append(&path)?);
+        Ok(())
+    }
+}
+
+
+/// Appends a batch of entries to the journal file, syncing it once.
+/// 
+fn write_entries(state: &Mutex<JournalState>, batch: Vec<(u64, Mutation)>) {
+    let mut state = state.lock();
+    let file = match state.file.as_mut() {
+        Some(file) => file,
+        None => {
+            log::warn(&format!("Journal is not opened, {} mutation(s) were not recorded.", batch.len()));
+            return;
+        }
+    };
+    let entries: Vec<JournalEntry> = batch.into_iter()
+        .map(|(seq, mutation)| JournalEntry { seq, mutation })
+        .collect();
+    let data = match serialize_entries(&entries) {
+        Ok(data) => data,
+        Err(err) => {
+            log::error(&format!("Failed serializing journal entries [{}].", err));
+            return;
+        }
+    };
+    if let Err(err) = file.write_all(&data).and_then(|_| file.sync_data()) {
+        // Mutations are still in memory, and will be part of next snapshot
+        log::error(&format!("Failed writing journal entries [{}].", err));
+    }
+}
+
+
+fn open_append(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("failed opening \"{}\" journal [{}]", path.display(), err))
+}
+
+
+fn serialize_entries(entries: &[JournalEntry]) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    for entry in entries {
+        let line = serde_json::to_string(entry)
+            .map_err(|err| format!("failed serializing journal entry [{}]", err))?;
+        buffer.extend_from_slice(line.as_bytes());
+        buffer.push(b'\n');
+    }
+    Ok(buffer)
+}
+
+
+/// Reads journal entries; reading stops at the first line that can't be
+/// parsed, which happens if the API stopped while appending it.
+/// 
+fn read_entries(path: &Path) -> Result<Vec<JournalEntry>, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(Vec::new())
+    };
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .map_err(|err| format!("failed reading \"{}\" journal [{}]", path.display(), err))?;
+
+    let mut entries = Vec::new();
+    for line in data.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+        match serde_json::from_slice::<JournalEntry>(line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => {
+                log::warn(&format!("Ignoring journal entries from an unreadable line [{}].", err));
+                break;
+            }
+        }
+    }
+    Ok(entries)
+}
+
+
+/// Applies journal entries that were written after the loaded snapshot, and
+/// starts journaling new mutations.
+/// 
+pub fn replay(store: &Store, journal: &Journal, path: &Path, snapshot_seq: u64) -> Result<(), String> {
+    let entries = apply_entries(store, path, snapshot_seq)?;
+    if !entries.is_empty() {
+        log::info(&format!("Replayed {} mutation(s) from \"{}\" journal.", entries.len(), path.display()));
+    }
+
+    let last_seq = entries.last().map(|e| e.seq).unwrap_or(snapshot_seq);
+    journal.open(path, &entries, last_seq)
+}
+
+
+/// Applies journal entries that were written after a snapshot, leaving the
+/// journal file untouched; returns applied entries.
+/// 
+pub fn apply_entries(store: &Store, path: &Path, snapshot_seq: u64) -> Result<Vec<JournalEntry>, String> {
+    let entries: Vec<JournalEntry> = read_entries(path)?
+        .into_iter()
+        .filter(|e| e.seq > snapshot_seq)
+        .collect();
+    for entry in &entries {
+        apply(store, entry.mutation.clone());
+    }
+    Ok(entries)
+}
+
+
+/// Applies a mutation to the store.
+/// 
+pub fn apply(store: &Store, mutation: Mutation) {
+    match mutation {
+        Mutation::PutEvent { event } => {
+            let event_id = event.id.clone().unwrap_or_default();
+            let mut events = store.events_list.write();
+            match events.iter().position(|e| e.id == event.id) {
+                Some(index) => events[index] = event,
+                None => events.push(event)
+            }
+            store.maps_list.write().entry(event_id).or_default();
+        },
+        Mutation::DeleteEvent { event_id } => {
+            store.events_list.write().retain(|e| e.id.as_deref() != Some(event_id.as_str()));
+            map::remove_event_maps(store, &event_id);
+        },
+        Mutation::PutMap { event_id, map } => {
+            let map_id = map.id.clone().unwrap_or_default();
+            let mut maps_list = store.maps_list.write();
+            let maps = maps_list.entry(event_id).or_default();
+            match maps.iter().position(|m| m.id == map.id) {
+                Some(index) => maps[index] = map,
+                None => maps.push(map)
+            }
+            store.routes_list.write().entry(map_id).or_default();
+        },
+        Mutation::DeleteMap { event_id, map_id } => {
+            if let Some(maps) = store.maps_list.write().get_mut(&event_id) {
+                maps.retain(|m| m.id.as_deref() != Some(map_id.as_str()));
+            }
+            route::remove_map_routes(store, &map_id);
+        },
+        Mutation::PutRoute { map_id, route } => {
+            let route_id = route.id.clone().unwrap_or_default();
+            let mut routes_list = store.routes_list.write();
+            let routes = routes_list.entry(map_id).or_default();
+            match routes.iter().position(|r| r.id == route.id) {
+                Some(index) => routes[index] = *route,
+                None => routes.push(*route)
+            }
+            store.scores_list.write().entry(route_id).or_default();
+        },
+        Mutation::DeleteRoute { map_id, route_id } => {
+            if let Some(routes) = store.routes_list.write().get_mut(&map_id) {
+                routes.retain(|r| r.id.as_deref() != Some(route_id.as_str()));
+            }
+            route::remove_route_scores(store, &route_id);
+        },
+        Mutation::PutScore { route_id, player, entry } => {
+            let mut scores_list = store.scores_list.write();
+            let scores = scores_list.entry(route_id.clone()).or_default();
+            scores.retain(|e| e.player_id() != player && e.player_id() != entry.player_id());
+            scores.push(entry.clone());
+            scores::sort(scores);
+            if let Some(runs) = store.runs_list.write().get_mut(&route_id) {
+                runs::update_player(runs, &player, &entry);
+            }
+        },
+        Mutation::DeleteScore { route_id, player } => {
+            if let Some(scores) = store.scores_list.write().get_mut(&route_id) {
+                scores.retain(|e| e.player_id() != player);
+            }
+            if let Some(runs) = store.runs_list.write().get_mut(&route_id) {
+                runs.retain(|r| r.entry.player_id() != player);
+            }
+        },
+        Mutation::AddRun { route_id, run } => {
+            let mut runs_list = store.runs_list.write();
+            let runs = runs_list.entry(route_id).or_default();
+            if !runs.iter().any(|r| r.id == run.id) {
+                runs.push(run);
+            }
+        },
+        Mutation::PutPlayer { player } => {
+            let mut scores_list = store.scores_list.write();
+            let mut runs_list = store.runs_list.write();
+            players::rename(&mut scores_list, &mut runs_list, &player.uid, &player.name);
+            store.players_list.write().insert(player.uid.clone(), player);
+        },
+        Mutation::FlagRun { run } => {
+            let mut flagged_runs = store.flagged_runs.write();
+            if !flagged_runs.iter().any(|r| r.id == run.id) {
+                flagged_runs.push(run);
+            }
+        },
+        Mutation::UnflagRun { run_id } => {
+            store.flagged_runs.write().retain(|r| r.id != run_id);
+        },
+        Mutation::RemoveOrphanRoutes { map_id } => {
+            route::remove_map_routes(store, &map_id);
+        },
+        Mutation::RemoveOrphanScores { route_id } => {
+            route::remove_route_scores(store, &route_id);
+        }
+    }
+}
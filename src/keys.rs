@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use parking_lot::{Mutex, RwLock};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use warp::{hyper::StatusCode, reject::Reject, Filter, Reply, Rejection};
+
+use crate::{Store, log, persistence};
+
+const KEYS_FILE: &str = "keys.json";
+
+/// Request header holding the API key.
+pub const KEY_HEADER: &str = "authentication";
+
+
+/// A permission granted to an API key.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Submit and edit scores
+    #[serde(rename = "scores:write")]
+    ScoresWrite,
+    /// Create, edit and delete events, maps and routes
+    #[serde(rename = "routes:write")]
+    RoutesWrite,
+    /// Everything, including keys management
+    #[serde(rename = "admin")]
+    Admin
+}
+
+impl Scope {
+    fn name(&self) -> &'static str {
+        match self {
+            Scope::ScoresWrite => "scores:write",
+            Scope::RoutesWrite => "routes:write",
+            Scope::Admin => "admin"
+        }
+    }
+}
+
+
+/// An API key, as stored in the registry; only a hash of the key itself is
+/// kept.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    hash: String,
+    /// Secret used to sign score submissions (keys issued before signatures
+    /// were introduced don't have one)
+    #[serde(default)]
+    signing_secret: Option<String>,
+    /// Whether score submissions made with this key must be signed
+    #[serde(default)]
+    pub signed: bool,
+    pub created: i64,
+    pub revoked: Option<i64>
+}
+
+impl ApiKey {
+    fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+
+    /// Key description returned by the API, which doesn't include its hash.
+    /// 
+    fn describe(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "scopes": self.scopes,
+            "signed": self.signed,
+            "created": self.created,
+            "revoked": self.revoked
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiKeyRequest {
+    name: String,
+    scopes: Vec<Scope>,
+    #[serde(default)]
+    signed: bool
+}
+
+
+/// What's needed to verify a request signed with a key.
+/// 
+pub struct Signer {
+    pub key_id: String,
+    pub signing_secret: Option<String>,
+    pub signed: bool
+}
+
+
+/// Reasons why a request was refused.
+/// 
+#[derive(Debug)]
+pub enum KeyRejection {
+    /// No key was provided, or it's unknown or revoked
+    Unauthenticated,
+    /// Provided key doesn't have the required scope
+    MissingScope(Scope)
+}
+
+impl Reject for KeyRejection {}
+
+
+/// Keys allowed to access the API, persisted in the data directory.
+/// 
+/// The `PARKOUR_API_SECRET` environment variable, if set, acts as an extra
+/// key with the `admin` scope (which is how the first keys get issued).
+/// 
+pub struct KeyRegistry {
+    path: PathBuf,
+    keys: RwLock<Vec<ApiKey>>,
+    root_hash: Option<String>,
+    /// Nonces of recently signed requests, with their timestamp
+    nonces: Mutex<HashMap<String, i64>>
+}
+
+impl KeyRegistry {
+    pub fn load(data_dir: &Path, root_key: Option<String>) -> Result<Self, String> {
+        let path = data_dir.join(KEYS_FILE);
+        let keys: Vec<ApiKey> = match File::open(&path) {
+            Ok(mut file) => {
+                let mut data = String::new();
+                file.read_to_string(&mut data)
+                    .map_err(|err| format!("failed reading \"{}\" file [{}]", path.display(), err))?;
+                serde_json::from_str(&data)
+                    .map_err(|err| format!("failed deserializing \"{}\" file [{}]", path.display(), err))?
+            },
+            Err(_) => Vec::new()
+        };
+
+        let active_count = keys.iter().filter(|k| k.revoked.is_none()).count();
+        log::info(&format!("Loaded {} active API key(s).", active_count));
+        if root_key.is_none() && !keys.iter().any(|k| k.revoked.is_none() && k.allows(Scope::Admin)) {
+            return Err(String::from("no admin key is available, PARKOUR_API_SECRET must be set"));
+        }
+
+        Ok(KeyRegistry {
+            path,
+            keys: RwLock::new(keys),
+            root_hash: root_key.map(|k| hash(&k)),
+            nonces: Mutex::new(HashMap::new())
+        })
+    }
+
+    /// Checks that a key exists and is allowed to use a scope (if any).
+    /// 
+    fn check(&self, key: Option<&str>, scope: Option<Scope>) -> Result<(), KeyRejection> {
+        let key_hash = match key {
+            Some(key) => hash(key),
+            None => return Err(KeyRejection::Unauthenticated)
+        };
+        if self.root_hash.as_ref() == Some(&key_hash) {
+            return Ok(());
+        }
+
+        let keys = self.keys.read();
+        let key = keys.iter()
+            .find(|k| k.revoked.is_none() && k.hash == key_hash)
+            .ok_or(KeyRejection::Unauthenticated)?;
+        match scope {
+            Some(scope) if !key.allows(scope) => Err(KeyRejection::MissingScope(scope)),
+            _ => Ok(())
+        }
+    }
+
+    /// Tells whether a key exists (and wasn't revoked).
+    /// 
+    pub fn is_valid(&self, key: &str) -> bool {
+        self.check(Some(key), None).is_ok()
+    }
+
+    /// Returns the name of a valid key, which identifies the game server using
+    /// it ("root" for the root key).
+    /// 
+    pub fn name(&self, key: &str) -> Option<String> {
+        let key_hash = hash(key);
+        if self.root_hash.as_ref() == Some(&key_hash) {
+            return Some(String::from("root"));
+        }
+        self.keys.read()
+            .iter()
+            .find(|k| k.revoked.is_none() && k.hash == key_hash)
+            .map(|k| k.name.clone())
+    }
+
+    /// Returns signing information of a valid key; the root key can't sign
+    /// requests.
+    /// 
+    pub fn signer(&self, key: &str) -> Option<Signer> {
+        let key_hash = hash(key);
+        if self.root_hash.as_ref() == Some(&key_hash) {
+            return Some(Signer { key_id: String::from("root"), signing_secret: None, signed: false });
+        }
+        self.keys.read()
+            .iter()
+            .find(|k| k.revoked.is_none() && k.hash == key_hash)
+            .map(|k| Signer { key_id: k.id.clone(), signing_secret: k.signing_secret.clone(), signed: k.signed })
+    }
+
+    /// Records the nonce of a signed request, returning false if it was
+    /// already used; nonces older than `max_age` seconds are forgotten, since
+    /// requests using them are refused anyway.
+    /// 
+    pub fn register_nonce(&self, key_id: &str, nonce: &str, timestamp: i64, max_age: i64) -> bool {
+        let now = Utc::now().timestamp();
+        let mut nonces = self.nonces.lock();
+        nonces.retain(|_, t| now - *t <= max_age);
+        nonces.insert(format!("{}:{}", key_id, nonce), timestamp).is_none()
+    }
+
+    fn save(&self, keys: &[ApiKey]) -> Result<(), String> {
+        let data = serde_json::to_vec(keys)
+            .map_err(|err| format!("failed serializing keys [{}]", err))?;
+        persistence::replace_file(&self.path, &data)
+    }
+}
+
+
+fn hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+
+fn random_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+
+/// This middleware rejects requests that don't hold a valid API key with the
+/// given scope (keys with the `admin` scope are allowed everything).
+/// 
+pub fn authorize(store: Store, scope: Scope) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>(KEY_HEADER)
+        .and_then(move |key: Option<String>| {
+            let store = store.clone();
+            async move {
+                store.keys.check(key.as_deref(), Some(scope)).map_err(warp::reject::custom)
+            }
+        })
+        .untuple_one()
+}
+
+
+/// This middleware extracts the name of the key a request was made with (if
+/// any), to tell which server submitted it.
+/// 
+pub fn key_name(store: Store) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(KEY_HEADER)
+        .map(move |key: Option<String>| key.and_then(|k| store.keys.name(&k)))
+}
+
+
+/// Turns key rejections into error responses; other rejections are left to
+/// warp.
+/// 
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<KeyRejection>() {
+        Some(KeyRejection::Unauthenticated) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Missing or invalid API key."})),
+            StatusCode::UNAUTHORIZED,
+        )),
+        Some(KeyRejection::MissingScope(scope)) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": format!("API key lacks \"{}\" scope.", scope.name())})),
+            StatusCode::FORBIDDEN,
+        )),
+        None => Err(rejection)
+    }
+}
+
+
+/// Returns the list of all keys (including revoked ones).
+/// 
+async fn get_list(
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let keys: Vec<serde_json::Value> = store.keys.keys.read().iter().map(|k| k.describe()).collect();
+        Ok(warp::reply::json(&keys))
+}
+
+
+/// This middleware creates `ApiKeyRequest` payloads from POST request bodies.
+/// 
+pub fn post_json() -> impl Filter<Extract = (ApiKeyRequest,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+
+/// Issues a new key; the key itself (and its signing secret) is only returned
+/// by this request.
+/// 
+async fn create_key(
+    request: ApiKeyRequest,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        if request.scopes.is_empty() {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "At least one scope is required."})),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        let mut keys = store.keys.keys.write();
+        if keys.iter().any(|k| k.revoked.is_none() && k.name == request.name) {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Key name already used."})),
+                StatusCode::ALREADY_REPORTED,
+            ));
+        }
+
+        let secret = random_secret();
+        let signing_secret = random_secret();
+        let key = ApiKey {
+            id: Uuid::new_v4().to_string(),
+            name: request.name,
+            scopes: request.scopes,
+            hash: hash(&secret),
+            signing_secret: Some(signing_secret.clone()),
+            signed: request.signed,
+            created: Utc::now().timestamp(),
+            revoked: None
+        };
+        keys.push(key.clone());
+        if let Err(err) = store.keys.save(&keys) {
+            keys.pop();
+            log::error(&format!("Failed saving API keys [{}].", err));
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Failed saving key."})),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+        log::info(&format!("Issued \"{}\" API key.", key.name));
+
+        let mut response = key.describe();
+        response["key"] = json!(secret);
+        response["signing_secret"] = json!(signing_secret);
+        Ok(warp::reply::with_status(
+            warp::reply::json(&response),
+            StatusCode::CREATED,
+        ))
+}
+
+
+/// Revokes a key; revoked keys are kept in the registry, but can't be used
+/// anymore.
+/// 
+async fn revoke_key(
+    key_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut keys = store.keys.keys.write();
+        let index = match keys.iter().position(|k| k.id == key_id) {
+            Some(index) => index,
+            None => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Key not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        };
+        if keys[index].revoked.is_some() {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Key already revoked."})),
+                StatusCode::ALREADY_REPORTED,
+            ));
+        }
+
+        keys[index].revoked = Some(Utc::now().timestamp());
+        if let Err(err) = store.keys.save(&keys) {
+            keys[index].revoked = None;
+            log::error(&format!("Failed saving API keys [{}].", err));
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Failed saving key."})),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+        log::info(&format!("Revoked \"{}\" API key.", keys[index].name));
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Key successfully revoked."})),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns all key-associated routes (which all require the `admin` scope):
+///     * one route to list all keys;
+///     * one route to issue a key;
+///     * one route to revoke a key.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let admin_filter = authorize(store.clone(), Scope::Admin);
+    let store_filter = warp::any().map(move || store.clone());
+
+    let get_all_keys = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("keys"))
+        .and(warp::path::end())
+        .and(admin_filter.clone())
+        .and(store_filter.clone())
+        .and_then(get_list);
+
+    let key_creation_route = warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path("keys"))
+        .and(warp::path::end())
+        .and(admin_filter.clone())
+        .and(post_json())
+        .and(store_filter.clone())
+        .and_then(create_key);
+
+    let key_revocation_route = warp::delete()
+        .and(warp::path("v1"))
+        .and(warp::path("keys"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(admin_filter)
+        .and(store_filter)
+        .and_then(revoke_key);
+
+    get_all_keys
+        .or(key_creation_route)
+        .or(key_revocation_route)
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use warp::{hyper::StatusCode, Filter, Reply, Rejection};
+
+use crate::{Store, event::Event, scores::ScoreEntry};
+
+/// Points awarded by default to the first positions of each route.
+const DEFAULT_POINTS: [u32; 10] = [25, 18, 15, 12, 10, 8, 6, 4, 2, 1];
+
+
+/// How results on all routes of an event are combined into one ranking.
+/// 
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Scoring {
+    /// Sum of the player's ranks on all routes (lowest wins); a route the
+    /// player has no time on counts as one rank below its last player
+    #[default]
+    RankSum,
+    /// Sum of points awarded per route position (highest wins); positions
+    /// beyond the table award no points
+    Points {
+        #[serde(default = "default_points")]
+        table: Vec<u32>
+    },
+    /// Sum of the player's times (lowest wins); only players who completed
+    /// every route are ranked
+    TimeSum
+}
+
+impl Scoring {
+    /// Describes the score of a standing, as displayed on the scoreboard.
+    /// 
+    pub fn label(&self) -> &'static str {
+        match self {
+            Scoring::RankSum => "Rank sum",
+            Scoring::Points { .. } => "Points",
+            Scoring::TimeSum => "Total time (seconds)"
+        }
+    }
+
+    fn highest_wins(&self) -> bool {
+        matches!(self, Scoring::Points { .. })
+    }
+}
+
+fn default_points() -> Vec<u32> {
+    DEFAULT_POINTS.to_vec()
+}
+
+
+/// Query parameters of the event leaderboard, overriding the event scoring.
+/// 
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardQuery {
+    /// One of `rank_sum`, `points` or `time_sum`
+    scoring: Option<String>,
+    /// Comma-separated points table, used with `points` scoring
+    points: Option<String>
+}
+
+impl LeaderboardQuery {
+    /// Builds the scoring requested by the query, falling back to the event
+    /// scoring (or to its points table, if only the method is given).
+    /// 
+    fn scoring(&self, event_scoring: Scoring) -> Result<Scoring, String> {
+        let method = match &self.scoring {
+            Some(method) => method.as_str(),
+            None => return Ok(event_scoring)
+        };
+        match method {
+            "rank_sum" => Ok(Scoring::RankSum),
+            "time_sum" => Ok(Scoring::TimeSum),
+            "points" => {
+                let table = match (&self.points, event_scoring) {
+                    (Some(points), _) => points.split(',')
+                        .map(|p| p.trim().parse::<u32>())
+                        .collect::<Result<Vec<u32>, _>>()
+                        .map_err(|_| String::from("Invalid points table."))?,
+                    (None, Scoring::Points { table }) => table,
+                    (None, _) => default_points()
+                };
+                Ok(Scoring::Points { table })
+            },
+            _ => Err(String::from("Unknown scoring method."))
+        }
+    }
+}
+
+
+/// A player's position on the overall ranking of an event.
+/// 
+#[derive(Debug, Serialize, Clone)]
+pub struct Standing {
+    pub rank: usize,
+    pub player_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    pub name: String,
+    /// Rank sum, points or total time, depending on scoring
+    pub score: f32,
+    /// Number of routes the player has a time on
+    pub completed: usize
+}
+
+/// Overall ranking of an event, across all routes of all its maps.
+/// 
+#[derive(Debug, Serialize, Clone)]
+pub struct Leaderboard {
+    pub event_id: String,
+    pub event_name: String,
+    pub scoring: Scoring,
+    pub route_count: usize,
+    pub standings: Vec<Standing>
+}
+
+
+/// Computes the overall ranking of an event.
+/// 
+pub fn compute(store: &Store, event: &Event, scoring: Scoring) -> Leaderboard {
+    let event_id = event.id.clone().unwrap_or_default();
+    let maps_list = store.maps_list.read();
+    let routes_list = store.routes_list.read();
+    let scores_list = store.scores_list.read();
+
+    let mut leaderboards: Vec<&[ScoreEntry]> = Vec::new();
+    for map in maps_list.get(&event_id).into_iter().flatten() {
+        let map_id = map.id.clone().unwrap_or_default();
+        for route in routes_list.get(&map_id).into_iter().flatten() {
+            let route_id = route.id.clone().unwrap_or_default();
+            leaderboards.push(scores_list.get(&route_id).map(|s| s.as_slice()).unwrap_or_default());
+        }
+    }
+
+    // Players are listed in order of first appearance, so that ties are
+    // sorted the same way on every request
+    let mut indexes: HashMap<&str, usize> = HashMap::new();
+    let mut standings: Vec<Standing> = Vec::new();
+    for entry in leaderboards.iter().flat_map(|scores| scores.iter()) {
+        indexes.entry(entry.player_id()).or_insert_with(|| {
+            standings.push(Standing {
+                rank: 0,
+                player_id: entry.player_id().to_string(),
+                uid: entry.uid.clone(),
+                name: entry.name.clone(),
+                score: 0.0,
+                completed: 0
+            });
+            standings.len() - 1
+        });
+    }
+
+    for scores in &leaderboards {
+        let mut ranked = vec![false; standings.len()];
+        // Leaderboard is sorted, so entries share their predecessor's rank
+        // when they have the same time
+        let mut previous: Option<(f32, usize)> = None;
+        for (position, entry) in scores.iter().enumerate() {
+            let index = indexes[entry.player_id()];
+            let rank = match previous {
+                Some((time, rank)) if time == entry.time => rank,
+                _ => position + 1
+            };
+            previous = Some((entry.time, rank));
+            let standing = &mut standings[index];
+            standing.completed += 1;
+            standing.score += match &scoring {
+                Scoring::RankSum => rank as f32,
+                Scoring::Points { table } => table.get(rank - 1).copied().unwrap_or(0) as f32,
+                Scoring::TimeSum => entry.time
+            };
+            ranked[index] = true;
+        }
+        if scoring == Scoring::RankSum {
+            for (standing, _) in standings.iter_mut().zip(ranked).filter(|(_, ranked)| !ranked) {
+                standing.score += (scores.len() + 1) as f32;
+            }
+        }
+    }
+
+    if scoring == Scoring::TimeSum {
+        standings.retain(|s| s.completed == leaderboards.len());
+    }
+    if scoring.highest_wins() {
+        standings.sort_by(|a, b| b.score.total_cmp(&a.score));
+    } else {
+        standings.sort_by(|a, b| a.score.total_cmp(&b.score));
+    }
+
+    // Players with the same score share their predecessor's rank
+    let mut previous: Option<(f32, usize)> = None;
+    for (index, standing) in standings.iter_mut().enumerate() {
+        standing.rank = match previous {
+            Some((score, rank)) if score == standing.score => rank,
+            _ => index + 1
+        };
+        previous = Some((standing.score, standing.rank));
+    }
+
+    Leaderboard {
+        event_id,
+        event_name: event.name.clone(),
+        scoring,
+        route_count: leaderboards.len(),
+        standings
+    }
+}
+
+
+/// Returns the overall ranking of an event, using the event scoring unless
+/// another one is requested.
+/// 
+async fn get_leaderboard(
+    event_id: String,
+    query: LeaderboardQuery,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let event = match store.events_list.read().iter().find(|e| e.id.as_deref() == Some(event_id.as_str())) {
+            Some(event) => event.clone(),
+            None => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        };
+
+        let scoring = match query.scoring(event.scoring.clone().unwrap_or_default()) {
+            Ok(scoring) => scoring,
+            Err(err) => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": err})),
+                StatusCode::BAD_REQUEST,
+            ))
+        };
+        let leaderboard = compute(&store, &event, scoring);
+        Ok(warp::reply::with_status(
+            warp::reply::json(&leaderboard),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns all leaderboard-associated routes:
+///     * one route to get the overall ranking of an event.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let store_filter = warp::any().map(move || store.clone());
+
+    warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path("leaderboard"))
+        .and(warp::path::end())
+        .and(warp::query::<LeaderboardQuery>())
+        .and(store_filter)
+        .and_then(get_leaderboard)
+}
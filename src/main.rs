@@ -1,35 +1,65 @@
+mod config;
 mod persistence;
+mod journal;
+mod storage;
+mod sqlite;
+mod integrity;
+mod keys;
+mod ratelimit;
+mod signing;
 pub mod log;
 pub mod map;
 mod event;
 mod scores;
+mod runs;
+mod players;
+mod stats;
+mod leaderboard;
+mod notifications;
+mod webhooks;
+mod moderation;
 pub mod route;
 mod scoreboard;
 
 use event::Events;
 use map::Maps;
 use route::MapRoutes;
-use persistence::{start_save_cron, load_state};
+use keys::KeyRegistry;
+use storage::{Storage, start_save_cron};
 use warp::Filter;
 use parking_lot::RwLock;
-use std::{env, sync::Arc, collections::HashMap};
+use std::{env, fs::create_dir_all, sync::Arc, collections::HashMap};
 
 
 #[derive(Clone)]
 pub struct Store {
   events_list: Arc<RwLock<Events>>,  
   scores_list: Arc<RwLock<scores::ScoreEntries>>,
+  runs_list: Arc<RwLock<runs::Runs>>,
   maps_list: Arc<RwLock<Maps>>,
-  routes_list: Arc<RwLock<MapRoutes>>
+  routes_list: Arc<RwLock<MapRoutes>>,
+  flagged_runs: Arc<RwLock<moderation::FlaggedRuns>>,
+  players_list: Arc<RwLock<players::Players>>,
+  storage: Arc<dyn Storage>,
+  keys: Arc<KeyRegistry>,
+  webhooks: Arc<webhooks::WebhookRegistry>,
+  notifier: Arc<notifications::Notifier>
 }
 
 impl Store {
-    fn new() -> Self {
+    fn new(storage: Arc<dyn Storage>, keys: Arc<KeyRegistry>, webhooks: Arc<webhooks::WebhookRegistry>) -> Self {
         Store {
             events_list: Arc::new(RwLock::new(Vec::new())),
             scores_list: Arc::new(RwLock::new(HashMap::new())),
+            runs_list: Arc::new(RwLock::new(HashMap::new())),
             maps_list: Arc::new(RwLock::new(HashMap::new())),
-            routes_list: Arc::new(RwLock::new(HashMap::new()))
+            routes_list: Arc::new(RwLock::new(HashMap::new())),
+            flagged_runs: Arc::new(RwLock::new(Vec::new())),
+            players_list: Arc::new(RwLock::new(HashMap::new())),
+            storage,
+            keys,
+            webhooks,
+            notifier: Arc::new(notifications::Notifier::new())
         }
     }
 }
@@ -37,47 +67,141 @@ impl Store {
 
 #[tokio::main]
 async fn main() {
-    // Secret key
-    let secret = match env::var("PARKOUR_API_SECRET") {
-        Ok(s) => s,
+    let config = config::load();
+
+    // Data directory holds API keys, webhooks and state
+    if let Err(err) = create_dir_all(&config.data_dir) {
+        log::error(&format!("Failed creating \"{}\" data directory, exiting [{}].", config.data_dir.display(), err));
+        std::process::exit(1);
+    }
+
+    // API keys (the secret acts as an admin key)
+    let keys = match KeyRegistry::load(&config.data_dir, env::var("PARKOUR_API_SECRET").ok()) {
+        Ok(keys) => keys,
         Err(err) => {
-            log::error(&format!("No secret was found, exiting [{}].", err));
+            log::error(&format!("Failed loading API keys, exiting [{}].", err));
             std::process::exit(1);
         }
     };
 
-    // Authentication control
-    let header_value = Box::leak(secret.into_boxed_str());
-    let accept_requests = warp::header::exact("authentication", header_value);
+    let webhooks = match webhooks::WebhookRegistry::load(&config.data_dir) {
+        Ok(webhooks) => webhooks,
+        Err(err) => {
+            log::error(&format!("Failed loading webhooks, exiting [{}].", err));
+            std::process::exit(1);
+        }
+    };
 
-    let store = Store::new();
+    let store = Store::new(storage::from_config(&config), Arc::new(keys), Arc::new(webhooks));
 
-    // If scores were previously saved to file, restore them
-    load_state(store.clone());
+    // If state was previously saved, restore it
+    if let Err(err) = store.storage.load(&store) {
+        log::error(&format!("Failed loading state [{}].", err));
+        std::process::exit(2);
+    }
+    integrity::check(&store, config.repair_integrity);
     // Scores saving cron
-    start_save_cron(store.clone());
+    start_save_cron(store.clone(), config.save_timer_minutes);
+    notifications::start_dispatcher(store.clone());
+    webhooks::start_delivery(store.clone());
 
     // Routes
     let map_routes = map::get_routes(store.clone());
     let event_routes = event::get_routes(store.clone());
     let score_routes = scores::get_routes(store.clone());
+    let run_routes = runs::get_routes(store.clone());
+    let player_routes = players::get_routes(store.clone());
+    let stats_routes = stats::get_routes(store.clone());
+    let leaderboard_routes = leaderboard::get_routes(store.clone());
     let map_route_routes = route::get_routes(store.clone());
-    let routes = event_routes.or(map_routes).or(score_routes).or(map_route_routes);
-
-    // Authentication middleware
-    let routes = accept_requests.and(routes);
-
-    // Serve scoreboard route only if there are registered events
-    if store.clone().events_list.read().len() >= 1 {
-        let scoreboard_route = scoreboard::get_routes(store);
-        let new_routes = routes.or(scoreboard_route);
-        warp::serve(new_routes)
-            .run(([0, 0, 0, 0], 3030))
-            .await;
-    } else {
-        log::warn("Not serving scoreboard since no events were found.");
-        warp::serve(routes)
-            .run(([0, 0, 0, 0], 3030))
-            .await;
+    let key_routes = keys::get_routes(store.clone());
+    let moderation_routes = moderation::get_routes(store.clone());
+    let notification_routes = notifications::get_routes(store.clone());
+    let webhook_routes = webhooks::get_routes(store.clone());
+    let routes = event_routes
+        .or(map_routes)
+        .or(score_routes)
+        .or(run_routes)
+        .or(player_routes)
+        .or(stats_routes)
+        .or(leaderboard_routes)
+        .or(map_route_routes)
+        .or(key_routes)
+        .or(moderation_routes)
+        .or(notification_routes)
+        .or(webhook_routes);
+
+    // Reading routes are public (but may be rate limited), while mutating
+    // routes check API key scopes
+    let limiter = Arc::new(ratelimit::RateLimiter::new(config.rate_limit));
+    let routes = ratelimit::limit(limiter, store.clone()).and(routes);
+
+    // Scoreboard can't be served without its templates, but the API still is
+    match scoreboard::get_routes(store.clone(), &config.scoreboard_dir, config.template_reload_seconds) {
+        Ok(scoreboard_route) => {
+            let new_routes = routes
+                .or(scoreboard_route)
+                .recover(keys::handle_rejection)
+                .recover(signing::handle_rejection)
+                .recover(ratelimit::handle_rejection);
+            let (_, server) = warp::serve(new_routes)
+                .bind_with_graceful_shutdown(config.address, shutdown_signal(store.notifier.clone()));
+            server.await;
+        },
+        Err(err) => {
+            log::warn(&format!("Not serving scoreboard [{}].", err));
+            let new_routes = routes
+                .recover(keys::handle_rejection)
+                .recover(signing::handle_rejection)
+                .recover(ratelimit::handle_rejection);
+            let (_, server) = warp::serve(new_routes)
+                .bind_with_graceful_shutdown(config.address, shutdown_signal(store.notifier.clone()));
+            server.await;
+        }
+    }
+
+    // In-flight requests have been handled, store state won't change anymore
+    store.storage.commit().wait().await;
+    store.webhooks.commit().wait().await;
+    match store.storage.save(&store) {
+        Ok(_) => log::info("Saved state before exiting."),
+        Err(err) => {
+            log::error(&format!("Failed saving state before exiting [{}].", err));
+            std::process::exit(3);
+        }
+    }
+}
+
+
+/// Resolves when the process is asked to stop (through SIGINT, or SIGTERM on
+/// Unix platforms); live notification subscriptions are then ended.
+/// 
+async fn shutdown_signal(notifier: Arc<notifications::Notifier>) {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            log::error(&format!("Failed listening for interrupt signal [{}].", err));
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut stream) => { stream.recv().await; },
+            Err(err) => {
+                log::error(&format!("Failed listening for termination signal [{}].", err));
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => (),
+        _ = terminate => ()
     }
+    log::info("Shutdown signal received, finishing in-flight requests.");
+    notifier.close();
 }
\ No newline at end of file
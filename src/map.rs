@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 
 use serde::{Serialize, Deserialize};
+use serde_json::json;
 use uuid::Uuid;
 use warp::{Filter, hyper::StatusCode, Reply, Rejection};
 
-use crate::{Store, event::Event};
+use crate::{Store, storage, keys::{self, Scope}, event::Event, journal::Mutation, route};
 
 pub type Maps = HashMap<String, Vec<Map>>;
 
@@ -14,6 +15,17 @@ pub struct Map {
     pub id: Option<String>
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MapPatch {
+    map_name: Option<String>
+}
+
+impl From<Map> for MapPatch {
+    fn from(map: Map) -> Self {
+        MapPatch { map_name: Some(map.map_name) }
+    }
+}
+
 
 /// Gets the list of maps associated to a given event.
 /// 
@@ -26,7 +38,7 @@ async fn get_list(
     let index = events.iter().position(|e| e.id.clone().unwrap() == event_id).unwrap_or(usize::MAX);
     if index == usize::MAX {
         return Ok(warp::reply::with_status(
-            warp::reply::json(&"{\"error\": \"Event not found.\"}"),
+            warp::reply::json(&json!({"error": "Event not found."})),
             StatusCode::NOT_FOUND,
         ));
     }
@@ -40,6 +52,38 @@ async fn get_list(
 }
 
 
+/// Looks up a map across all events, returning it along with the identifier of
+/// the event it belongs to.
+/// 
+pub fn find_map(store: &Store, map_id: &str) -> Option<(String, Map)> {
+    let read_lock = store.maps_list.read();
+    read_lock.iter().find_map(|(event_id, maps)| {
+        maps.iter()
+            .find(|m| m.id.as_deref() == Some(map_id))
+            .map(|m| (event_id.clone(), m.clone()))
+    })
+}
+
+
+/// Returns a single map, based on its identifier.
+/// 
+async fn get_map(
+    map_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+    match find_map(&store, &map_id) {
+        Some((_, map)) => Ok(warp::reply::with_status(
+            warp::reply::json(&map),
+            StatusCode::OK,
+        )),
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Map not found."})),
+            StatusCode::NOT_FOUND,
+        ))
+    }
+}
+
+
 /// Creates a map that's associated to the input event.
 /// 
 async fn create_map(
@@ -47,37 +91,36 @@ async fn create_map(
     entry: Map,
     store: Store
     ) -> Result<impl Reply, Rejection> {
-        // Check if the event exists
-        let events: Vec<Event> = store.events_list.read().to_vec();
-        let index = events.iter().position(|e| e.id.clone().unwrap() == event_id).unwrap_or(usize::MAX);
-        if index == usize::MAX {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&"{\"error\": \"Event not found.\"}"),
+        // Maps list is held until the map is recorded, so that a concurrent
+        // event deletion either happens before (and the event isn't found) or
+        // after (and removes the new map)
+        let mut write_lock = store.maps_list.write();
+        let maps = match write_lock.get_mut(&event_id) {
+            Some(maps) => maps,
+            None => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event not found."})),
                 StatusCode::NOT_FOUND,
-            ));
-        }
+            ))
+        };
 
         // Checking for existing map
-        let mut maps: Vec<Map> = store.maps_list.read().get(&event_id).unwrap().to_vec();
-        let index = maps.iter().position(|e| e.map_name == entry.map_name).unwrap_or(usize::MAX);
-        if index != usize::MAX {
+        if maps.iter().any(|m| m.map_name == entry.map_name) {
             return Ok(warp::reply::with_status(
-                warp::reply::json(&"{\"error\": \"Map already exists.\"}"),
+                warp::reply::json(&json!({"error": "Map already exists."})),
                 StatusCode::ALREADY_REPORTED,
             ));
         }
 
         let map_id = Uuid::new_v4().to_string();
-        let mut write_lock = store.maps_list.write();
-        maps.push(Map { map_name: entry.map_name, id: Some(map_id.clone()) });
-        write_lock.insert(event_id, maps);
+        let map = Map { map_name: entry.map_name, id: Some(map_id.clone()) };
+        maps.push(map.clone());
 
         // Create associated routes array
-        let mut routes_write_lock = store.routes_list.write();
-        routes_write_lock.insert(map_id, [].to_vec());
+        store.routes_list.write().insert(map_id, Vec::new());
+        store.storage.record(Mutation::PutMap { event_id, map });
 
         Ok(warp::reply::with_status(
-            warp::reply::json(&"{\"message\": \"Map successfully created.\"}"),
+            warp::reply::json(&json!({"message": "Map successfully created."})),
             StatusCode::CREATED,
         ))
 }
@@ -90,11 +133,127 @@ pub fn post_json() -> impl Filter<Extract = (Map,), Error = Rejection> + Clone {
 }
 
 
+/// This middleware creates `MapPatch` payloads from PATCH request bodies.
+/// 
+pub fn patch_json() -> impl Filter<Extract = (MapPatch,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+
+/// Replaces all fields of an existing map.
+/// 
+async fn replace_map(
+    event_id: String,
+    map_id: String,
+    entry: Map,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        update_map(event_id, map_id, MapPatch::from(entry), store).await
+}
+
+
+/// Updates fields of an existing map; fields absent from the patch are left
+/// untouched.
+/// 
+async fn update_map(
+    event_id: String,
+    map_id: String,
+    patch: MapPatch,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut write_lock = store.maps_list.write();
+        let maps = match write_lock.get_mut(&event_id) {
+            Some(maps) => maps,
+            None => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": "Event not found."})),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+        };
+        let index = maps.iter().position(|m| m.id.as_deref() == Some(map_id.as_str())).unwrap_or(usize::MAX);
+        if index == usize::MAX {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Map not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+
+        if let Some(map_name) = patch.map_name {
+            if maps.iter().enumerate().any(|(i, m)| i != index && m.map_name == map_name) {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": "Map already exists."})),
+                    StatusCode::ALREADY_REPORTED,
+                ));
+            }
+            maps[index].map_name = map_name;
+        }
+        store.storage.record(Mutation::PutMap { event_id, map: maps[index].clone() });
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Map successfully updated."})),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Deletes a map, as well as all its routes and their scores.
+/// 
+async fn delete_map(
+    event_id: String,
+    map_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut write_lock = store.maps_list.write();
+        let maps = match write_lock.get_mut(&event_id) {
+            Some(maps) => maps,
+            None => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": "Event not found."})),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+        };
+        let index = maps.iter().position(|m| m.id.as_deref() == Some(map_id.as_str())).unwrap_or(usize::MAX);
+        if index == usize::MAX {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Map not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+        maps.remove(index);
+        route::remove_map_routes(&store, &map_id);
+        store.storage.record(Mutation::DeleteMap { event_id, map_id });
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Map successfully deleted."})),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Removes all maps associated to an event, cascading to their routes and scores.
+/// 
+pub fn remove_event_maps(store: &Store, event_id: &str) {
+    let maps = store.maps_list.write().remove(event_id).unwrap_or_default();
+    for map in maps {
+        if let Some(map_id) = map.id {
+            route::remove_map_routes(store, &map_id);
+        }
+    }
+}
+
+
 /// Returns all map-associated routes:
 ///     * one route to list an event's maps;
-///     * one route to create maps on a given event.
+///     * one route to get a map;
+///     * one route to create maps on a given event;
+///     * one route to replace a map;
+///     * one route to partially update a map;
+///     * one route to delete a map.
 /// 
 pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let write_filter = keys::authorize(store.clone(), Scope::RoutesWrite);
     let store_filter = warp::any().map(move || store.clone());
 
     let map_list_route = warp::get()
@@ -106,15 +265,72 @@ pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error =
         .and(store_filter.clone())
         .and_then(get_list);
 
+    let get_map_route = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("maps"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(get_map);
+
     let map_creation_route = warp::post()
         .and(warp::path("v1"))
         .and(warp::path("events"))
         .and(warp::path::param())
         .and(warp::path("maps"))
         .and(warp::path::end())
+        .and(write_filter.clone())
         .and(post_json())
+        .and(store_filter.clone())
+        .and_then(create_map)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let map_replacement_route = warp::put()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path("maps"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(post_json())
+        .and(store_filter.clone())
+        .and_then(replace_map)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let map_update_route = warp::patch()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path("maps"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(patch_json())
+        .and(store_filter.clone())
+        .and_then(update_map)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let map_deletion_route = warp::delete()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path("maps"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(store_filter.clone())
+        .and_then(delete_map)
         .and(store_filter)
-        .and_then(create_map);
+        .and_then(storage::wait_commit);
 
-    map_list_route.or(map_creation_route)
+    map_list_route
+        .or(get_map_route)
+        .or(map_creation_route)
+        .or(map_replacement_route)
+        .or(map_update_route)
+        .or(map_deletion_route)
 }
@@ -0,0 +1,191 @@
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use uuid::Uuid;
+use warp::{hyper::StatusCode, Filter, Reply, Rejection};
+
+use crate::{Store, storage, keys::{self, Scope}, journal::Mutation, log, route::MapRoute, runs::{self, Run}, scores::ScoreEntry};
+
+pub type FlaggedRuns = Vec<FlaggedRun>;
+
+/// Routes need that many scores before their times are used to spot
+/// suspicious runs (when they have no minimum time).
+const STATISTICAL_MIN_SCORES: usize = 10;
+
+/// Runs faster than this ratio of the median time of a route are suspicious.
+const STATISTICAL_RATIO: f32 = 0.5;
+
+
+/// A run that looked suspicious, held out of the leaderboard until an admin
+/// reviews it.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FlaggedRun {
+    pub id: String,
+    pub route_id: String,
+    pub entry: ScoreEntry,
+    pub reason: String,
+    pub submitted: i64,
+    /// Name of the API key the run was submitted with
+    #[serde(default)]
+    pub server: Option<String>
+}
+
+impl FlaggedRun {
+    pub fn new(route_id: String, entry: ScoreEntry, reason: String, server: Option<String>) -> Self {
+        FlaggedRun { id: Uuid::new_v4().to_string(), route_id, entry, reason, submitted: Utc::now().timestamp(), server }
+    }
+}
+
+
+/// Tells why a run looks suspicious, if it does: it's faster than the route
+/// minimum plausible time or, for routes without one, much faster than the
+/// median time of the leaderboard.
+/// 
+pub fn check(route: &MapRoute, scores: &[ScoreEntry], entry: &ScoreEntry) -> Option<String> {
+    if let Some(min_time) = route.min_time() {
+        if entry.time < min_time {
+            return Some(format!("time is lower than route minimum time ({})", min_time));
+        }
+        return None;
+    }
+
+    // Scores are sorted by time
+    if scores.len() >= STATISTICAL_MIN_SCORES {
+        let median = scores[scores.len() / 2].time;
+        if entry.time < median * STATISTICAL_RATIO {
+            return Some(format!("time is lower than {} times the median time ({})", STATISTICAL_RATIO, median));
+        }
+    }
+    None
+}
+
+
+/// Returns the list of runs awaiting moderation.
+/// 
+async fn get_list(
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let r = store.flagged_runs.read();
+        Ok(warp::reply::json(&*r))
+}
+
+
+/// Adds a flagged run to its route history (registering its player), and to
+/// its leaderboard (unless the player already has a better score there).
+/// 
+/// Runs of deleted routes are dropped along with them, but the route is still
+/// checked, so that the run isn't removed from the queue if it's missing.
+/// 
+async fn approve_run(
+    run_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut scores_list = store.scores_list.write();
+        let mut runs_list = store.runs_list.write();
+        let mut flagged_runs = store.flagged_runs.write();
+        let index = match flagged_runs.iter().position(|r| r.id == run_id) {
+            Some(index) => index,
+            None => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Run not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        };
+        if !scores_list.contains_key(&flagged_runs[index].route_id) {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Route not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+        let run = flagged_runs.remove(index);
+        store.storage.record(Mutation::UnflagRun { run_id });
+
+        let approved = Run::new(run.entry, run.submitted, run.server);
+        if !runs::record(&store, &run.route_id, &mut scores_list, &mut runs_list, approved) {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"message": "Run approved, but leaderboard contains a better score entry for this player."})),
+                StatusCode::ALREADY_REPORTED,
+            ));
+        }
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Run approved."})),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Drops a flagged run.
+/// 
+async fn reject_run(
+    run_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut flagged_runs = store.flagged_runs.write();
+        let index = match flagged_runs.iter().position(|r| r.id == run_id) {
+            Some(index) => index,
+            None => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Run not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        };
+        let run = flagged_runs.remove(index);
+        store.storage.record(Mutation::UnflagRun { run_id });
+        log::info(&format!("Rejected run of {} on route {} ({}).", run.entry.name, run.route_id, run.entry.time));
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Run rejected."})),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns all moderation-associated routes (which all require the `admin`
+/// scope):
+///     * one route to list runs awaiting moderation;
+///     * one route to approve a run;
+///     * one route to reject a run.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let admin_filter = keys::authorize(store.clone(), Scope::Admin);
+    let store_filter = warp::any().map(move || store.clone());
+
+    let get_flagged_runs = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("moderation"))
+        .and(warp::path("runs"))
+        .and(warp::path::end())
+        .and(admin_filter.clone())
+        .and(store_filter.clone())
+        .and_then(get_list);
+
+    let run_approval_route = warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path("moderation"))
+        .and(warp::path("runs"))
+        .and(warp::path::param())
+        .and(warp::path("approve"))
+        .and(warp::path::end())
+        .and(admin_filter.clone())
+        .and(store_filter.clone())
+        .and_then(approve_run)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let run_rejection_route = warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path("moderation"))
+        .and(warp::path("runs"))
+        .and(warp::path::param())
+        .and(warp::path("reject"))
+        .and(warp::path::end())
+        .and(admin_filter)
+        .and(store_filter.clone())
+        .and_then(reject_run)
+        .and(store_filter)
+        .and_then(storage::wait_commit);
+
+    get_flagged_runs
+        .or(run_approval_route)
+        .or(run_rejection_route)
+}
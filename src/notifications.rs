@@ -0,0 +1,226 @@
+use std::{sync::mpsc, thread};
+
+use futures_util::{future, SinkExt, Stream, StreamExt};
+use parking_lot::Mutex;
+use serde::{Serialize, Deserialize};
+use tokio::sync::{broadcast, watch};
+use tokio_stream::wrappers::BroadcastStream;
+use warp::{sse, ws::{Message, WebSocket}, Filter, Reply, Rejection};
+
+use crate::{Store, log, route, scores::ScoreEntry, webhooks};
+
+/// Number of notifications a slow subscriber can fall behind by before
+/// missing some.
+const CHANNEL_CAPACITY: usize = 256;
+
+
+/// A change subscribers are notified of.
+/// 
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// A run entered its route leaderboard
+    ScoreCreated { entry: ScoreEntry, rank: usize },
+    /// A run beat the best time of its route (or is the first one on it)
+    NewRecord { entry: ScoreEntry, previous: Option<ScoreEntry> },
+    RouteCreated { map_id: String, name: String }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Notification {
+    /// Event the route belongs to (unknown if the route was deleted in the
+    /// meantime)
+    pub event_id: Option<String>,
+    pub route_id: String,
+    #[serde(flatten)]
+    pub kind: NotificationKind
+}
+
+impl Notification {
+    fn name(&self) -> &'static str {
+        match self.kind {
+            NotificationKind::ScoreCreated { .. } => "score_created",
+            NotificationKind::NewRecord { .. } => "new_record",
+            NotificationKind::RouteCreated { .. } => "route_created"
+        }
+    }
+}
+
+
+/// Broadcasts store changes to live subscribers.
+/// 
+/// Notifications are published while store locks are held, so they're queued
+/// and completed (with the event their route belongs to) by a dispatcher
+/// thread, which is free to lock the store (and hands new records to
+/// webhooks).
+/// 
+pub struct Notifier {
+    queue: mpsc::Sender<(String, NotificationKind)>,
+    pending: Mutex<Option<mpsc::Receiver<(String, NotificationKind)>>>,
+    channel: broadcast::Sender<Notification>,
+    closed: watch::Sender<bool>
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        let (queue, pending) = mpsc::channel();
+        Notifier {
+            queue,
+            pending: Mutex::new(Some(pending)),
+            channel: broadcast::channel(CHANNEL_CAPACITY).0,
+            closed: watch::channel(false).0
+        }
+    }
+
+    /// Notifies subscribers of a change on a route.
+    /// 
+    pub fn publish(&self, route_id: &str, kind: NotificationKind) {
+        // Dispatcher never stops, so queue is always open
+        let _ = self.queue.send((route_id.to_string(), kind));
+    }
+
+    /// Returns notifications matching a filter as they're published; the
+    /// stream ends when the notifier is closed.
+    /// 
+    pub fn subscribe(&self, filter: NotificationFilter) -> impl Stream<Item = Notification> {
+        let mut closed = self.closed.subscribe();
+        BroadcastStream::new(self.channel.subscribe())
+            // Notifications missed by a lagging subscriber are skipped
+            .filter_map(move |n| future::ready(n.ok().filter(|n| filter.matches(n))))
+            .take_until(async move {
+                let _ = closed.wait_for(|closed| *closed).await;
+            })
+    }
+
+    /// Ends all subscriptions, so that open connections don't prevent the API
+    /// from stopping.
+    /// 
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+}
+
+
+/// Starts completing and broadcasting published notifications.
+/// 
+pub fn start_dispatcher(store: Store) {
+    let pending = match store.notifier.pending.lock().take() {
+        Some(pending) => pending,
+        None => return
+    };
+    thread::spawn(move || {
+        for (route_id, kind) in pending {
+            let notification = Notification { event_id: find_event(&store, &route_id), route_id, kind };
+            webhooks::enqueue(&store, &notification);
+            // Sending only fails when nobody is subscribed
+            let _ = store.notifier.channel.send(notification);
+        }
+    });
+}
+
+
+fn find_event(store: &Store, route_id: &str) -> Option<String> {
+    let (map_id, _) = route::find_route(store, route_id)?;
+    let maps_list = store.maps_list.read();
+    maps_list.iter()
+        .find(|(_, maps)| maps.iter().any(|m| m.id.as_deref() == Some(map_id.as_str())))
+        .map(|(event_id, _)| event_id.clone())
+}
+
+
+/// Query parameters of notification subscriptions; a subscription without
+/// parameters receives all notifications.
+/// 
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct NotificationFilter {
+    event_id: Option<String>,
+    route_id: Option<String>
+}
+
+impl NotificationFilter {
+    fn matches(&self, notification: &Notification) -> bool {
+        self.event_id.as_ref().map_or(true, |id| notification.event_id.as_ref() == Some(id))
+            && self.route_id.as_ref().map_or(true, |id| &notification.route_id == id)
+    }
+}
+
+
+/// Streams notifications as Server-Sent Events, named after their type.
+/// 
+async fn stream_events(
+    filter: NotificationFilter,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let events = store.notifier.subscribe(filter)
+            .map(|n| sse::Event::default().event(n.name()).json_data(&n));
+        Ok(sse::reply(sse::keep_alive().stream(events)))
+}
+
+
+/// Streams notifications as JSON text messages over a WebSocket, until either
+/// side closes it; messages sent by clients are ignored.
+/// 
+async fn stream_socket(socket: WebSocket, filter: NotificationFilter, store: Store) {
+    let (mut sender, mut receiver) = socket.split();
+    let notifications = store.notifier.subscribe(filter);
+    tokio::pin!(notifications);
+
+    loop {
+        tokio::select! {
+            notification = notifications.next() => {
+                let notification = match notification {
+                    Some(notification) => notification,
+                    None => break
+                };
+                let text = match serde_json::to_string(&notification) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        log::error(&format!("Failed serializing notification [{}].", err));
+                        continue;
+                    }
+                };
+                if sender.send(Message::text(text)).await.is_err() {
+                    break;
+                }
+            },
+            message = receiver.next() => match message {
+                Some(Ok(message)) if !message.is_close() => continue,
+                _ => break
+            }
+        }
+    }
+    let _ = sender.close().await;
+}
+
+
+/// Returns all notification-associated routes:
+///     * one route to receive notifications as Server-Sent Events;
+///     * one route to receive notifications over a WebSocket.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let store_filter = warp::any().map(move || store.clone());
+
+    let sse_route = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("notifications"))
+        .and(warp::path("sse"))
+        .and(warp::path::end())
+        .and(warp::query::<NotificationFilter>())
+        .and(store_filter.clone())
+        .and_then(stream_events);
+
+    let ws_route = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("notifications"))
+        .and(warp::path("ws"))
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(warp::query::<NotificationFilter>())
+        .and(store_filter)
+        .map(|ws: warp::ws::Ws, filter: NotificationFilter, store: Store| {
+            ws.on_upgrade(move |socket| stream_socket(socket, filter, store))
+        });
+
+    sse_route.or(ws_route)
+}
+
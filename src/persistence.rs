@@ -1,271 +1,675 @@
-use std::env;
-use std::fs::create_dir_all;
-use std::{thread, time::Duration, fs::File};
+use std::fs::{copy, create_dir_all, read_dir, remove_dir_all, rename, File};
+use std::path::{Path, PathBuf};
 use std::io::prelude::*;
 
+use chrono::Local;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use serde_json::{json, Map as JsonObject, Value};
+
 use crate::event::Events;
 use crate::map::Maps;
 use crate::route::MapRoutes;
 use crate::scores::ScoreEntries;
+use crate::runs::Runs;
+use crate::players::Players;
+use crate::moderation::FlaggedRuns;
+use crate::journal::{self, Journal, Mutation};
+use crate::storage::{Commit, Storage};
 use crate::{Store, log};
 
-const EVENTS_FILE: &str = "data/events.json";
-const MAPS_FILE: &str = "data/maps.json";
-const SCORES_FILE: &str = "data/scores.json";
-const ROUTES_FILE: &str = "data/routes.json";
+const SNAPSHOTS_DIR: &str = "snapshots";
+const BACKUPS_DIR: &str = "backups";
+const MANIFEST_FILE: &str = "manifest.json";
+const EVENTS_FILE: &str = "events.json";
+const MAPS_FILE: &str = "maps.json";
+const SCORES_FILE: &str = "scores.json";
+const ROUTES_FILE: &str = "routes.json";
+const RUNS_FILE: &str = "runs.json";
+const PLAYERS_FILE: &str = "players.json";
+const FLAGGED_RUNS_FILE: &str = "flagged_runs.json";
+const JOURNAL_FILE: &str = "journal.jsonl";
 
+/// Number of snapshot generations kept on disk (including the current one).
+const KEPT_GENERATIONS: usize = 2;
 
-/// Starts a thread that will save store state to JSON files every few seconds.
+/// Version of the persisted state format; each time it changes, a migration
+/// from the previous version must be added to `MIGRATIONS`.
 /// 
-/// The time between two consecutive saves is 15 minutes by default, and can be
-/// customized with the `PARKOUR_API_SAVE_TIMER` environment variable.
+/// Versions match the releases that introduced them:
+///     * 1 (0.0.1): maps had a single route, and scores were keyed by map;
+///     * 2 (0.0.2): maps have several routes, and scores are keyed by route;
+///     * 3 (0.0.3): routes have a `route_name` entity;
+///     * 4 (0.0.4): route entities have a `hidden` flag;
+///     * 5 (0.0.5): all runs are kept, along with the best score of each player.
+const SCHEMA_VERSION: u32 = 5;
+
+/// Migrations upgrading state from a version to the next one; the migration
+/// at index `i` upgrades state from version `i + 1`.
+const MIGRATIONS: [fn(&mut RawState); (SCHEMA_VERSION - 1) as usize] = [
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5
+];
+
+
+/// The manifest is the commit point of a snapshot: it designates which
+/// generation directory holds the current state.
 /// 
-pub fn start_save_cron(store: Store) {
-    let cron_interval_minutes: u64 = match env::var("PARKOUR_API_SAVE_TIMER") {
-        Ok(s) => {
-            log::info(&format!("Timer argument found ({} minutes).", s));
-            s.parse::<u64>().unwrap()
-        },
-        Err(_) => {
-            log::info("No timer argument was found, defaulting to 15 minutes.");
-            15
-        }
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct Manifest {
+    generation: u64,
+    /// Sequence number of the last journal entry included in the snapshot
+    #[serde(default)]
+    journal_seq: u64,
+    /// Version of the state format used by the snapshot
+    #[serde(default = "unversioned_manifest_version")]
+    version: u32
+}
+
+/// Manifests were introduced in version 4, before getting a version field.
+/// 
+fn unversioned_manifest_version() -> u32 {
+    4
+}
+
+
+/// State files content, before being deserialized into store collections.
+/// 
+struct RawState {
+    events: Value,
+    maps: Value,
+    routes: Value,
+    scores: Value,
+    runs: Value,
+    players: Value,
+    flagged_runs: Value
+}
+
+
+/// Stores state as JSON files under the data directory: a snapshot is
+/// written on each save, and mutations happening between two saves are kept
+/// in a journal.
+/// 
+pub struct JsonStorage {
+    data_dir: PathBuf,
+    journal: Journal,
+    /// Prevents the save cron and the shutdown save from writing the same
+    /// generation concurrently
+    save_lock: Mutex<()>
+}
+
+impl JsonStorage {
+    pub fn new(data_dir: &Path) -> Self {
+        JsonStorage { data_dir: data_dir.to_path_buf(), journal: Journal::new(), save_lock: Mutex::new(()) }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&self, store: &Store) -> Result<(), String> {
+        load_state(store, &self.journal, &self.data_dir);
+        Ok(())
+    }
+
+    fn record(&self, mutation: Mutation) {
+        self.journal.append(mutation);
+    }
+
+    fn commit(&self) -> Commit {
+        self.journal.commit()
+    }
+
+    fn save(&self, store: &Store) -> Result<(), String> {
+        let _guard = self.save_lock.lock();
+        let generation = save_state(store, &self.journal, &self.data_dir)?;
+        log::info(&format!("Saved state to local files (generation {}).", generation));
+        Ok(())
+    }
+}
+
+
+/// Writes a consistent snapshot of the store to disk.
+/// 
+/// All collections are written to a fresh generation directory, and
+/// fsynced; the generation is then committed by atomically replacing the
+/// manifest file. A crash at any point thus leaves either the previous or
+/// the new generation in place, never a mix of both.
+/// 
+/// Once committed, journal entries included in the snapshot are discarded.
+/// 
+fn save_state(store: &Store, journal: &Journal, data_dir: &Path) -> Result<u64, String> {
+    // Mutations are journaled after being applied, so all entries up to this
+    // one are included in the state read below
+    let journal_seq = journal.last_seq();
+
+    // Lock all collections at once (in the same order as request handlers) so
+    // that files agree on which identifiers exist
+    let files = {
+        let events = store.events_list.read();
+        let maps = store.maps_list.read();
+        let routes = store.routes_list.read();
+        let scores = store.scores_list.read();
+        let runs = store.runs_list.read();
+        let flagged_runs = store.flagged_runs.read();
+        let players = store.players_list.read();
+        [
+            (EVENTS_FILE, to_json(&*events)?),
+            (MAPS_FILE, to_json(&*maps)?),
+            (ROUTES_FILE, to_json(&*routes)?),
+            (SCORES_FILE, to_json(&*scores)?),
+            (RUNS_FILE, to_json(&*runs)?),
+            (PLAYERS_FILE, to_json(&*players)?),
+            (FLAGGED_RUNS_FILE, to_json(&*flagged_runs)?)
+        ]
     };
 
-    thread::spawn(move || {
-        loop {
-            thread::sleep(Duration::from_secs(cron_interval_minutes * 60));
+    let generation = write_generation(data_dir, &files, journal_seq)?;
 
-            // Create data directory if needed
-            match create_dir_all("data") {
-                Ok(_) => (),
-                Err(err) => {
-                    log::error(&format!("Failed creating data directory [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-
-            // Scores
-            let scores = store.scores_list.read().clone();
-            let mut buffer = match File::create(SCORES_FILE) {
-                Ok(file) => file,
-                Err(err) => {
-                    log::error(&format!("\"{}\" file could not be created [{}].", SCORES_FILE, err));
-                    std::process::exit(3);
-                }
-            };
-            let str = match serde_json::to_string(&scores) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed serializing scores list [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            match buffer.write_all(str.as_bytes()) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed writing scores list to file [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            log::info("Saved scores to local file.");
-
-            // Maps
-            let maps = store.maps_list.read().clone();
-            let mut buffer = match File::create(MAPS_FILE) {
-                Ok(file) => file,
-                Err(err) => {
-                    log::error(&format!("\"{}\" file could not be created [{}].", MAPS_FILE, err));
-                    std::process::exit(3);
-                }
-            };
-            let str = match serde_json::to_string(&maps) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed serializing maps list [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            match buffer.write_all(str.as_bytes()) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed writing maps list to file [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            log::info("Saved maps to local file.");
-
-            // Events
-            let events = store.events_list.read().clone();
-            let mut buffer = match File::create(EVENTS_FILE) {
-                Ok(file) => file,
-                Err(err) => {
-                    log::error(&format!("\"{}\" file could not be created [{}].", EVENTS_FILE, err));
-                    std::process::exit(3);
-                }
-            };
-            let str = match serde_json::to_string(&events) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed serializing events list [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            match buffer.write_all(str.as_bytes()) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed writing events list to file [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            log::info("Saved events to local file.");
-
-            // Routes
-            let routes = store.routes_list.read().clone();
-            let mut buffer = match File::create(ROUTES_FILE) {
-                Ok(file) => file,
-                Err(err) => {
-                    log::error(&format!("\"{}\" file could not be created [{}].", ROUTES_FILE, err));
-                    std::process::exit(3);
-                }
-            };
-            let str = match serde_json::to_string(&routes) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed serializing routes list [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            match buffer.write_all(str.as_bytes()) {
-                Ok(str) => str,
-                Err(err) => {
-                    log::error(&format!("Failed writing routes list to file [{}].", err));
-                    std::process::exit(3);
-                }
-            };
-            log::info("Saved routes to local file.");
-        }
-    });
+    if let Err(err) = journal.compact(journal_seq) {
+        log::warn(&format!("Failed compacting journal [{}].", err));
+    }
+    Ok(generation)
 }
 
 
-/// Called when the API is started, this method checks if state was previously
-/// stored in JSON files, and loads up store state from them if possible.
+/// Serializes a collection, so that it can be written once all collections
+/// are read.
 /// 
-pub fn load_state(store: Store) {
-    // Scores
-    let mut file = match File::open(SCORES_FILE) {
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(value)
+        .map_err(|err| format!("failed serializing state [{}]", err))
+}
+
+
+/// Writes state files (file names along with their content) to a new
+/// snapshot generation, and commits it.
+/// 
+fn write_generation(data_dir: &Path, files: &[(&str, Vec<u8>)], journal_seq: u64) -> Result<u64, String> {
+    let generation = read_manifest(data_dir)?.map(|m| m.generation + 1).unwrap_or(1);
+    let generation_dir = generation_dir(data_dir, generation);
+
+    // A previous attempt may have left an uncommitted directory behind
+    if generation_dir.exists() {
+        remove_dir_all(&generation_dir)
+            .map_err(|err| format!("failed cleaning \"{}\" directory [{}]", generation_dir.display(), err))?;
+    }
+    create_dir_all(&generation_dir)
+        .map_err(|err| format!("failed creating \"{}\" directory [{}]", generation_dir.display(), err))?;
+
+    for (file_name, content) in files {
+        write_bytes(&generation_dir.join(file_name), content)?;
+    }
+    sync_dir(&generation_dir)?;
+
+    // Commit
+    write_atomic(&data_dir.join(MANIFEST_FILE), &Manifest { generation, journal_seq, version: SCHEMA_VERSION })?;
+
+    remove_old_generations(data_dir, generation);
+    Ok(generation)
+}
+
+
+/// Writes content to a file, and flushes it to disk.
+/// 
+fn write_bytes(path: &Path, content: &[u8]) -> Result<(), String> {
+    let mut buffer = File::create(path)
+        .map_err(|err| format!("\"{}\" file could not be created [{}]", path.display(), err))?;
+    buffer.write_all(content)
+        .map_err(|err| format!("failed writing \"{}\" file [{}]", path.display(), err))?;
+    buffer.sync_all()
+        .map_err(|err| format!("failed syncing \"{}\" file [{}]", path.display(), err))
+}
+
+
+/// Serializes a value to a file atomically.
+/// 
+fn write_atomic<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let str = serde_json::to_string(value)
+        .map_err(|err| format!("failed serializing \"{}\" content [{}]", path.display(), err))?;
+    replace_file(path, str.as_bytes())
+}
+
+
+/// Replaces a file atomically, by writing to a temporary file that is then
+/// renamed over the target.
+/// 
+pub fn replace_file(path: &Path, content: &[u8]) -> Result<(), String> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    write_bytes(&tmp_path, content)?;
+    rename(&tmp_path, path)
+        .map_err(|err| format!("failed renaming \"{}\" to \"{}\" [{}]", tmp_path.display(), path.display(), err))?;
+    match path.parent() {
+        Some(parent) => sync_dir(parent),
+        None => Ok(())
+    }
+}
+
+
+/// Flushes directory entries to disk, so that created/renamed files survive
+/// a crash (this is a no-op on platforms that can't open directories).
+/// 
+fn sync_dir(path: &Path) -> Result<(), String> {
+    if cfg!(unix) {
+        let dir = File::open(path)
+            .map_err(|err| format!("failed opening \"{}\" directory [{}]", path.display(), err))?;
+        dir.sync_all()
+            .map_err(|err| format!("failed syncing \"{}\" directory [{}]", path.display(), err))?;
+    }
+    Ok(())
+}
+
+
+fn generation_dir(data_dir: &Path, generation: u64) -> PathBuf {
+    data_dir.join(SNAPSHOTS_DIR).join(generation.to_string())
+}
+
+
+/// Reads the manifest, if any snapshot was ever committed.
+/// 
+fn read_manifest(data_dir: &Path) -> Result<Option<Manifest>, String> {
+    let path = data_dir.join(MANIFEST_FILE);
+    let mut file = match File::open(&path) {
         Ok(file) => file,
-        Err(_) => {
-            log::info(&format!("\"{}\" file does not exist, initializing scores list as empty.", SCORES_FILE));
-            return;
-        }
+        Err(_) => return Ok(None)
     };
     let mut data = String::new();
-    match file.read_to_string(&mut data) {
-        Ok(_) => (),
-        Err(err) => {
-            log::error(&format!("Failed reading \"{}\" file [{}].", SCORES_FILE, err));
-            std::process::exit(2);
-        }
+    file.read_to_string(&mut data)
+        .map_err(|err| format!("failed reading \"{}\" file [{}]", path.display(), err))?;
+    serde_json::from_str::<Manifest>(&data)
+        .map(Some)
+        .map_err(|err| format!("failed deserializing \"{}\" file [{}]", path.display(), err))
+}
+
+
+/// Deletes snapshot generations that are not among the most recent ones.
+/// 
+fn remove_old_generations(data_dir: &Path, current: u64) {
+    let entries = match read_dir(data_dir.join(SNAPSHOTS_DIR)) {
+        Ok(entries) => entries,
+        Err(_) => return
     };
-    let serialized: ScoreEntries = match serde_json::from_str::<ScoreEntries>(&data) {
-        Ok(data) => data,
-        Err(err) => {
-            log::error(&format!("Failed serializing scores list [{}].", err));
-            std::process::exit(2);
+    for entry in entries.flatten() {
+        let generation = match entry.file_name().to_string_lossy().parse::<u64>() {
+            Ok(generation) => generation,
+            Err(_) => continue
+        };
+        if generation + (KEPT_GENERATIONS as u64) <= current {
+            if let Err(err) = remove_dir_all(entry.path()) {
+                log::warn(&format!("Failed removing old snapshot \"{}\" [{}].", entry.path().display(), err));
+            }
         }
-    };
-    let mut write_lock = store.scores_list.write();
-    for (key, value) in serialized {
-        write_lock.insert(key, value);
     }
-    log::info(&format!("Loaded scores list from \"{}\" file.", SCORES_FILE));
+}
 
-    // Maps
-    let mut file = match File::open(MAPS_FILE) {
+
+/// Reads a state file as a JSON value (missing files are read as empty
+/// objects, or empty arrays for lists).
+/// 
+fn read_raw_file(path: &Path, empty: Value) -> Result<Value, String> {
+    let mut file = match File::open(path) {
         Ok(file) => file,
-        Err(_) => {
-            log::info(&format!("\"{}\" file does not exist, initializing maps list as empty.", MAPS_FILE));
-            return;
-        }
+        Err(_) => return Ok(empty)
     };
     let mut data = String::new();
-    match file.read_to_string(&mut data) {
-        Ok(_) => (),
-        Err(err) => {
-            log::error(&format!("Failed reading \"{}\" file [{}].", MAPS_FILE, err));
-            std::process::exit(2);
-        }
+    file.read_to_string(&mut data)
+        .map_err(|err| format!("failed reading \"{}\" file [{}]", path.display(), err))?;
+    serde_json::from_str(&data)
+        .map_err(|err| format!("failed deserializing \"{}\" file [{}]", path.display(), err))
+}
+
+
+fn read_raw_state(state_dir: &Path) -> Result<RawState, String> {
+    Ok(RawState {
+        events: read_raw_file(&state_dir.join(EVENTS_FILE), json!([]))?,
+        maps: read_raw_file(&state_dir.join(MAPS_FILE), json!({}))?,
+        routes: read_raw_file(&state_dir.join(ROUTES_FILE), json!({}))?,
+        scores: read_raw_file(&state_dir.join(SCORES_FILE), json!({}))?,
+        runs: read_raw_file(&state_dir.join(RUNS_FILE), json!({}))?,
+        players: read_raw_file(&state_dir.join(PLAYERS_FILE), json!({}))?,
+        flagged_runs: read_raw_file(&state_dir.join(FLAGGED_RUNS_FILE), json!([]))?
+    })
+}
+
+
+/// Guesses the format version of state files that were stored before
+/// versioning was introduced.
+/// 
+fn detect_version(state_dir: &Path) -> u32 {
+    let routes = match read_raw_file(&state_dir.join(ROUTES_FILE), json!({})) {
+        Ok(routes) => routes,
+        // Let loading report the error
+        Err(_) => return SCHEMA_VERSION
     };
-    let serialized: Maps = match serde_json::from_str::<Maps>(&data) {
-        Ok(data) => data,
-        Err(err) => {
-            log::error(&format!("Failed deserializing maps list [{}].", err));
-            std::process::exit(2);
-        }
+    let routes = match routes.as_object() {
+        Some(routes) => routes,
+        None => return SCHEMA_VERSION
     };
-    let mut write_lock = store.maps_list.write();
-    for (key, value) in serialized {
-        write_lock.insert(key, value);
+    if routes.values().any(|r| r.is_object()) {
+        1
+    } else if routes.values().flat_map(|r| r.as_array().cloned().unwrap_or_default()).any(|r| r.get("route_name").is_none()) {
+        2
+    } else if routes.is_empty() {
+        SCHEMA_VERSION
+    } else {
+        3
     }
-    log::info(&format!("Loaded maps list from \"{}\" file.", MAPS_FILE));
+}
 
-    // Events
-    let mut file = match File::open(EVENTS_FILE) {
-        Ok(file) => file,
-        Err(_) => {
-            log::info(&format!("\"{}\" file does not exist, initializing events list as empty.", EVENTS_FILE));
-            return;
+
+/// Upgrades state files to the current format version; files are backed up
+/// before being migrated, and migrated state is written as a new snapshot
+/// generation, whose number is returned.
+/// 
+fn migrate_state(data_dir: &Path, state_dir: &Path, version: u32, journal_seq: u64) -> Result<u64, String> {
+    // Backup
+    let backup_dir = data_dir
+        .join(BACKUPS_DIR)
+        .join(format!("{}-v{}", Local::now().format("%Y%m%d%H%M%S"), version));
+    create_dir_all(&backup_dir)
+        .map_err(|err| format!("failed creating \"{}\" directory [{}]", backup_dir.display(), err))?;
+    for file_name in [EVENTS_FILE, MAPS_FILE, ROUTES_FILE, SCORES_FILE, RUNS_FILE, PLAYERS_FILE, FLAGGED_RUNS_FILE] {
+        let path = state_dir.join(file_name);
+        if path.exists() {
+            copy(&path, backup_dir.join(file_name))
+                .map_err(|err| format!("failed backing up \"{}\" file [{}]", path.display(), err))?;
         }
-    };
-    let mut data = String::new();
-    match file.read_to_string(&mut data) {
-        Ok(_) => (),
+    }
+    log::info(&format!("Backed up state files to \"{}\" directory.", backup_dir.display()));
+
+    let mut state = read_raw_state(state_dir)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip((version - 1) as usize) {
+        migration(&mut state);
+        log::info(&format!("Migrated state from format version {} to {}.", index + 1, index + 2));
+    }
+    let files = [
+        (EVENTS_FILE, to_json(&state.events)?),
+        (MAPS_FILE, to_json(&state.maps)?),
+        (ROUTES_FILE, to_json(&state.routes)?),
+        (SCORES_FILE, to_json(&state.scores)?),
+        (RUNS_FILE, to_json(&state.runs)?),
+        (PLAYERS_FILE, to_json(&state.players)?),
+        (FLAGGED_RUNS_FILE, to_json(&state.flagged_runs)?)
+    ];
+    write_generation(data_dir, &files, journal_seq)
+}
+
+
+/// 0.0.2 allowed maps to have several routes: routes are now stored as lists
+/// (and got identifiers), and scores are keyed by route instead of map.
+/// 
+fn migrate_v1_to_v2(state: &mut RawState) {
+    let mut routes = JsonObject::new();
+    let mut scores = JsonObject::new();
+    let mut old_scores = state.scores.as_object().cloned().unwrap_or_default();
+
+    for (map_id, mut route) in state.routes.as_object().cloned().unwrap_or_default() {
+        let route_id = match route.get("id").and_then(|id| id.as_str()) {
+            Some(id) => id.to_string(),
+            None => uuid::Uuid::new_v4().to_string()
+        };
+        route["id"] = json!(route_id);
+        scores.insert(route_id, old_scores.remove(&map_id).unwrap_or(json!([])));
+        routes.insert(map_id, json!([route]));
+    }
+    for map_id in old_scores.keys() {
+        log::warn(&format!("Dropping scores of map {}, which has no route.", map_id));
+    }
+
+    // Maps that had no route get an empty routes list
+    for maps in state.maps.as_object().cloned().unwrap_or_default().values() {
+        for map in maps.as_array().cloned().unwrap_or_default() {
+            if let Some(map_id) = map.get("id").and_then(|id| id.as_str()) {
+                routes.entry(map_id).or_insert(json!([]));
+            }
+        }
+    }
+
+    state.routes = Value::Object(routes);
+    state.scores = Value::Object(scores);
+}
+
+
+/// 0.0.3 added the `route_name` entity to routes; it's placed on the start
+/// line by default.
+/// 
+fn migrate_v2_to_v3(state: &mut RawState) {
+    for route in routes_mut(state) {
+        if route.get("route_name").is_some() {
+            continue;
+        }
+        let start_line = route.get("start_line").cloned().unwrap_or(json!({}));
+        route["route_name"] = json!({
+            "origin": start_line.get("origin").cloned().unwrap_or(json!([0.0, 0.0, 0.0])),
+            "angles": start_line.get("angles").cloned().unwrap_or(json!([0, 0, 0])),
+            "dimensions": start_line.get("dimensions").cloned().unwrap_or(json!([120, 80]))
+        });
+    }
+}
+
+
+/// 0.0.4 added the `hidden` flag to route entities.
+/// 
+fn migrate_v3_to_v4(state: &mut RawState) {
+    for route in routes_mut(state) {
+        if let Some(entities) = route.get_mut("entities").and_then(|e| e.as_array_mut()) {
+            for entity in entities {
+                if entity.get("hidden").is_none() {
+                    entity["hidden"] = json!(false);
+                }
+            }
+        }
+    }
+}
+
+
+/// 0.0.5 keeps all runs instead of only the best score of each player: the
+/// history starts with existing scores, whose submission time is unknown.
+/// 
+fn migrate_v4_to_v5(state: &mut RawState) {
+    let mut runs = JsonObject::new();
+    for (route_id, scores) in state.scores.as_object().cloned().unwrap_or_default() {
+        let route_runs: Vec<Value> = scores.as_array().cloned().unwrap_or_default()
+            .into_iter()
+            .map(|mut entry| {
+                entry["id"] = json!(uuid::Uuid::new_v4().to_string());
+                entry["submitted"] = Value::Null;
+                entry["server"] = Value::Null;
+                entry
+            })
+            .collect();
+        runs.insert(route_id, json!(route_runs));
+    }
+    state.runs = Value::Object(runs);
+}
+
+
+fn routes_mut(state: &mut RawState) -> impl Iterator<Item = &mut Value> {
+    state.routes
+        .as_object_mut()
+        .into_iter()
+        .flat_map(|routes| routes.values_mut())
+        .filter_map(|routes| routes.as_array_mut())
+        .flatten()
+}
+
+
+/// Called when the API is started, this method checks if state was previously
+/// stored in JSON files, and loads up store state from them if possible; it
+/// then replays mutations that happened after the last save.
+/// 
+fn load_state(store: &Store, journal: &Journal, data_dir: &Path) {
+    // Use the committed generation if there's one, else the data directory
+    // itself (where state files were stored before snapshots were introduced)
+    let manifest = match read_manifest(data_dir) {
+        Ok(manifest) => manifest,
         Err(err) => {
-            log::error(&format!("Failed reading \"{}\" file [{}].", EVENTS_FILE, err));
+            log::error(&format!("Failed reading snapshot manifest [{}].", err));
             std::process::exit(2);
         }
     };
-    let serialized: Events = match serde_json::from_str::<Events>(&data) {
-        Ok(data) => data,
-        Err(err) => {
-            log::error(&format!("Failed deserializing events list [{}].", err));
-            std::process::exit(2);
+    let (mut state_dir, version) = match &manifest {
+        Some(manifest) => {
+            log::info(&format!("Loading state from snapshot generation {}.", manifest.generation));
+            (generation_dir(data_dir, manifest.generation), manifest.version)
+        },
+        None => (data_dir.to_path_buf(), detect_version(data_dir))
+    };
+    let journal_seq = manifest.map(|m| m.journal_seq).unwrap_or(0);
+
+    if version < 1 {
+        log::error(&format!("State was saved with invalid format version {}.", version));
+        std::process::exit(2);
+    }
+    if version > SCHEMA_VERSION {
+        log::error(&format!("State was saved with format version {}, which is newer than supported version {}.", version, SCHEMA_VERSION));
+        std::process::exit(2);
+    }
+    if version < SCHEMA_VERSION {
+        match migrate_state(data_dir, &state_dir, version, journal_seq) {
+            Ok(generation) => state_dir = generation_dir(data_dir, generation),
+            Err(err) => {
+                log::error(&format!("Failed migrating state from format version {} [{}].", version, err));
+                std::process::exit(2);
+            }
         }
+    }
+    load_snapshot(store, &state_dir);
+
+    if let Err(err) = create_dir_all(data_dir) {
+        log::error(&format!("Failed creating data directory [{}].", err));
+        std::process::exit(2);
+    }
+    if let Err(err) = journal::replay(store, journal, &data_dir.join(JOURNAL_FILE), journal_seq) {
+        log::error(&format!("Failed replaying journal [{}].", err));
+        std::process::exit(2);
+    }
+}
+
+
+/// Reads state stored as JSON files into the store without writing anything,
+/// so that it can be imported into another storage backend: older formats are
+/// migrated in memory, and journal entries are applied without rewriting the
+/// journal.
+/// 
+pub fn read_state(store: &Store, data_dir: &Path) -> Result<(), String> {
+    let manifest = read_manifest(data_dir)?;
+    let (state_dir, version) = match &manifest {
+        Some(manifest) => (generation_dir(data_dir, manifest.generation), manifest.version),
+        None => (data_dir.to_path_buf(), detect_version(data_dir))
     };
-    let mut write_lock = store.events_list.write();
-    for value in serialized {
-        write_lock.push(value);
+    if version < 1 {
+        return Err(format!("state was saved with invalid format version {}", version));
+    }
+    if version > SCHEMA_VERSION {
+        return Err(format!("state was saved with format version {}, which is newer than supported version {}", version, SCHEMA_VERSION));
+    }
+
+    let mut state = read_raw_state(&state_dir)?;
+    for migration in MIGRATIONS.iter().skip((version - 1) as usize) {
+        migration(&mut state);
+    }
+    *store.events_list.write() = from_raw(state.events, EVENTS_FILE)?;
+    *store.maps_list.write() = from_raw(state.maps, MAPS_FILE)?;
+    *store.routes_list.write() = from_raw(state.routes, ROUTES_FILE)?;
+    *store.scores_list.write() = from_raw(state.scores, SCORES_FILE)?;
+    *store.runs_list.write() = from_raw(state.runs, RUNS_FILE)?;
+    *store.players_list.write() = from_raw(state.players, PLAYERS_FILE)?;
+    *store.flagged_runs.write() = from_raw(state.flagged_runs, FLAGGED_RUNS_FILE)?;
+
+    let journal_seq = manifest.map(|m| m.journal_seq).unwrap_or(0);
+    journal::apply_entries(store, &data_dir.join(JOURNAL_FILE), journal_seq)?;
+    Ok(())
+}
+
+
+fn from_raw<T: DeserializeOwned>(value: Value, file_name: &str) -> Result<T, String> {
+    serde_json::from_value(value)
+        .map_err(|err| format!("failed deserializing \"{}\" content [{}]", file_name, err))
+}
+
+
+/// Loads store state from the JSON files of a directory; each collection is
+/// loaded independently, missing files leaving their collection empty.
+/// 
+fn load_snapshot(store: &Store, state_dir: &Path) {
+    let mut summary: Vec<String> = Vec::new();
+    let mut missing_files: Vec<String> = Vec::new();
+
+    // Events
+    if let Some(events) = load_file::<Events>(&state_dir.join(EVENTS_FILE), &mut missing_files) {
+        summary.push(format!("{} event(s)", events.len()));
+        store.events_list.write().extend(events);
+    }
+
+    // Maps
+    if let Some(maps) = load_file::<Maps>(&state_dir.join(MAPS_FILE), &mut missing_files) {
+        summary.push(format!("{} map(s)", maps.values().map(|m| m.len()).sum::<usize>()));
+        store.maps_list.write().extend(maps);
     }
-    log::info(&format!("Loaded events list from \"{}\" file.", EVENTS_FILE));
 
     // Routes
-    let mut file = match File::open(ROUTES_FILE) {
+    if let Some(routes) = load_file::<MapRoutes>(&state_dir.join(ROUTES_FILE), &mut missing_files) {
+        summary.push(format!("{} route(s)", routes.values().map(|r| r.len()).sum::<usize>()));
+        store.routes_list.write().extend(routes);
+    }
+
+    // Scores
+    if let Some(scores) = load_file::<ScoreEntries>(&state_dir.join(SCORES_FILE), &mut missing_files) {
+        summary.push(format!("{} score(s)", scores.values().map(|s| s.len()).sum::<usize>()));
+        store.scores_list.write().extend(scores);
+    }
+
+    // Runs
+    if let Some(runs) = load_file::<Runs>(&state_dir.join(RUNS_FILE), &mut missing_files) {
+        summary.push(format!("{} run(s)", runs.values().map(|r| r.len()).sum::<usize>()));
+        store.runs_list.write().extend(runs);
+    }
+
+    // Players and flagged runs (files written before they were introduced
+    // don't have any)
+    let mut optional_missing: Vec<String> = Vec::new();
+    if let Some(players) = load_file::<Players>(&state_dir.join(PLAYERS_FILE), &mut optional_missing) {
+        summary.push(format!("{} player(s)", players.len()));
+        store.players_list.write().extend(players);
+    }
+    if let Some(flagged_runs) = load_file::<FlaggedRuns>(&state_dir.join(FLAGGED_RUNS_FILE), &mut optional_missing) {
+        summary.push(format!("{} flagged run(s)", flagged_runs.len()));
+        store.flagged_runs.write().extend(flagged_runs);
+    }
+
+    if !summary.is_empty() {
+        log::info(&format!("Loaded {} from \"{}\" directory.", summary.join(", "), state_dir.display()));
+    }
+    if !missing_files.is_empty() {
+        log::info(&format!("Missing file(s) {}, initialized corresponding list(s) as empty.", missing_files.join(", ")));
+    }
+}
+
+
+/// Deserializes a state file; missing files are added to `missing_files`,
+/// while unreadable ones stop the API.
+/// 
+fn load_file<T: DeserializeOwned>(path: &Path, missing_files: &mut Vec<String>) -> Option<T> {
+    let mut file = match File::open(path) {
         Ok(file) => file,
         Err(_) => {
-            log::info(&format!("\"{}\" file does not exist, initializing routes list as empty.", ROUTES_FILE));
-            return;
+            missing_files.push(format!("\"{}\"", path.display()));
+            return None;
         }
     };
     let mut data = String::new();
-    match file.read_to_string(&mut data) {
-        Ok(_) => (),
-        Err(err) => {
-            log::error(&format!("Failed reading \"{}\" file [{}].", ROUTES_FILE, err));
-            std::process::exit(2);
-        }
-    };
-    let serialized: MapRoutes = match serde_json::from_str::<MapRoutes>(&data) {
-        Ok(data) => data,
+    if let Err(err) = file.read_to_string(&mut data) {
+        log::error(&format!("Failed reading \"{}\" file [{}].", path.display(), err));
+        std::process::exit(2);
+    }
+    match serde_json::from_str::<T>(&data) {
+        Ok(value) => Some(value),
         Err(err) => {
-            log::error(&format!("Failed deserializing routes list [{}].", err));
+            log::error(&format!("Failed deserializing \"{}\" file [{}].", path.display(), err));
             std::process::exit(2);
         }
-    };
-    let mut write_lock = store.routes_list.write();
-    for (key, value) in serialized {
-        write_lock.insert(key, value);
     }
-    log::info(&format!("Loaded routes list from \"{}\" file.", ROUTES_FILE));
 }
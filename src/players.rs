@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use warp::{hyper::StatusCode, Filter, Reply, Rejection};
+
+use crate::{Store, journal::Mutation, runs::{self, Runs}, scores::{self, ScoreEntries, ScoreEntry}};
+
+pub type Players = HashMap<String, Player>;
+
+
+/// A player, identified by their platform UID (such as their EA account
+/// identifier), whose name may change over time.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Player {
+    pub uid: String,
+    /// Latest known name, which is displayed on all leaderboards
+    pub name: String,
+    /// All names used by the player, oldest first
+    pub names: Vec<PlayerName>
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PlayerName {
+    pub name: String,
+    /// Timestamp of the first submission made under this name
+    pub since: i64
+}
+
+
+/// Registers the player of a score submission (if it holds a UID), and
+/// updates their name everywhere if it changed.
+/// 
+/// The first time a UID is seen, scores submitted without a UID under the same
+/// name are attributed to it.
+/// 
+/// This must only be done for accepted runs, while holding the leaderboards
+/// and run histories the run is added to.
+/// 
+pub fn register(store: &Store, scores_list: &mut ScoreEntries, runs_list: &mut Runs, entry: &ScoreEntry) {
+    let uid = match &entry.uid {
+        Some(uid) => uid,
+        None => return
+    };
+
+    let known = store.players_list.read().contains_key(uid);
+    if !known {
+        claim_entries(store, scores_list, runs_list, uid, &entry.name);
+    }
+    set_name(store, scores_list, runs_list, uid, &entry.name);
+}
+
+
+/// Attributes leaderboard entries that were submitted without a UID under a
+/// name (as well as the runs of those entries) to a player.
+/// 
+fn claim_entries(store: &Store, scores_list: &mut ScoreEntries, runs_list: &mut Runs, uid: &str, name: &str) {
+    for (route_id, scores) in scores_list.iter_mut() {
+        let entry = match scores.iter_mut().find(|e| e.uid.is_none() && e.name == name) {
+            Some(entry) => entry,
+            None => continue
+        };
+        entry.uid = Some(uid.to_string());
+        if let Some(runs) = runs_list.get_mut(route_id) {
+            runs::update_player(runs, name, entry);
+        }
+        store.storage.record(Mutation::PutScore { route_id: route_id.clone(), player: name.to_string(), entry: entry.clone() });
+    }
+}
+
+
+/// Sets the name of a player (registering them if needed), and renames all
+/// their leaderboard entries and runs; does nothing if the name didn't change.
+/// 
+pub fn set_name(store: &Store, scores_list: &mut ScoreEntries, runs_list: &mut Runs, uid: &str, name: &str) {
+    let mut players = store.players_list.write();
+    let player = players.entry(uid.to_string()).or_insert_with(|| Player {
+        uid: uid.to_string(),
+        name: String::new(),
+        names: Vec::new()
+    });
+    if player.name == name {
+        return;
+    }
+    player.name = name.to_string();
+    player.names.push(PlayerName { name: name.to_string(), since: Utc::now().timestamp() });
+    rename(scores_list, runs_list, uid, name);
+    store.storage.record(Mutation::PutPlayer { player: player.clone() });
+}
+
+
+/// Displays the latest name of a player on all their leaderboard entries and
+/// runs.
+/// 
+pub fn rename(scores_list: &mut ScoreEntries, runs_list: &mut Runs, uid: &str, name: &str) {
+    let entries = scores_list.values_mut().flatten()
+        .chain(runs_list.values_mut().flatten().map(|r| &mut r.entry));
+    for entry in entries.filter(|e| e.uid.as_deref() == Some(uid)) {
+        entry.name = name.to_string();
+    }
+}
+
+
+/// A player's result on a route.
+/// 
+#[derive(Serialize)]
+struct RouteResult {
+    map_id: String,
+    map_name: String,
+    route_id: String,
+    route_name: String,
+    time: f32,
+    rank: usize,
+    /// Difference between the player's time and the best time of the route
+    gap: f32,
+    /// Number of players on the route leaderboard
+    players: usize
+}
+
+/// A player's results on the routes of an event.
+/// 
+#[derive(Serialize)]
+struct EventResults {
+    event_id: String,
+    event_name: String,
+    /// Number of routes of the event
+    route_count: usize,
+    /// Number of routes the player has a time on
+    completed: usize,
+    total_time: f32,
+    routes: Vec<RouteResult>
+}
+
+/// A player, along with their results on all events.
+/// 
+#[derive(Serialize)]
+struct Profile {
+    id: String,
+    uid: Option<String>,
+    name: String,
+    names: Vec<PlayerName>,
+    events: Vec<EventResults>
+}
+
+
+/// Returns a player profile: their name history, and their time, rank and gap
+/// to first on all routes they completed, grouped by event.
+/// 
+async fn get_player(
+    player_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let player_id = scores::decode_player_name(&player_id);
+        let events = store.events_list.read();
+        let maps_list = store.maps_list.read();
+        let routes_list = store.routes_list.read();
+        let scores_list = store.scores_list.read();
+        let players = store.players_list.read();
+
+        let mut name: Option<String> = None;
+        let mut results: Vec<EventResults> = Vec::new();
+        for event in events.iter() {
+            let event_id = event.id.clone().unwrap_or_default();
+            let mut event_results = EventResults {
+                event_id: event_id.clone(),
+                event_name: event.name.clone(),
+                route_count: 0,
+                completed: 0,
+                total_time: 0.0,
+                routes: Vec::new()
+            };
+            let maps = maps_list.get(&event_id).into_iter().flatten();
+            for map in maps {
+                let map_id = map.id.clone().unwrap_or_default();
+                for route in routes_list.get(&map_id).into_iter().flatten() {
+                    event_results.route_count += 1;
+                    let route_id = route.id.clone().unwrap_or_default();
+                    let scores = match scores_list.get(&route_id) {
+                        Some(scores) => scores,
+                        None => continue
+                    };
+                    let entry = match scores.iter().find(|e| e.player_id() == player_id) {
+                        Some(entry) => entry,
+                        None => continue
+                    };
+                    name = Some(entry.name.clone());
+                    event_results.completed += 1;
+                    event_results.total_time += entry.time;
+                    event_results.routes.push(RouteResult {
+                        map_id: map_id.clone(),
+                        map_name: map.map_name.clone(),
+                        route_id,
+                        route_name: route.name.clone(),
+                        time: entry.time,
+                        rank: scores::rank(scores, entry.time),
+                        gap: entry.time - scores[0].time,
+                        players: scores.len()
+                    });
+                }
+            }
+            if event_results.completed > 0 {
+                results.push(event_results);
+            }
+        }
+
+        let player = players.get(&player_id);
+        let name = match (player, name) {
+            (Some(player), _) => player.name.clone(),
+            (None, Some(name)) => name,
+            (None, None) => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Player not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        };
+        let profile = Profile {
+            id: player_id,
+            uid: player.map(|p| p.uid.clone()),
+            name,
+            names: player.map(|p| p.names.clone()).unwrap_or_default(),
+            events: results
+        };
+        Ok(warp::reply::with_status(
+            warp::reply::json(&profile),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns all player-associated routes:
+///     * one route to get a player profile.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let store_filter = warp::any().map(move || store.clone());
+
+    warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("players"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(store_filter)
+        .and_then(get_player)
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde_json::json;
+use warp::{hyper::StatusCode, reject::Reject, Filter, Reply, Rejection};
+
+use crate::{Store, keys::KEY_HEADER};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+
+/// Requests counted for a client during the current window.
+/// 
+struct Window {
+    start: Instant,
+    count: u32
+}
+
+struct LimiterState {
+    windows: HashMap<IpAddr, Window>,
+    last_cleanup: Instant
+}
+
+
+/// Limits the number of API requests each anonymous client (identified by
+/// its IP address) can make per minute; requests holding a valid API key are
+/// never limited.
+/// 
+pub struct RateLimiter {
+    /// Requests allowed per minute, 0 disabling the limit
+    limit: u32,
+    state: Mutex<LimiterState>
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        RateLimiter {
+            limit,
+            state: Mutex::new(LimiterState { windows: HashMap::new(), last_cleanup: Instant::now() })
+        }
+    }
+
+    /// Counts a request from a client, returning the number of seconds it has
+    /// to wait if it went over the limit.
+    /// 
+    fn hit(&self, ip: IpAddr) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+
+        // Forget clients whose window has expired
+        if now.duration_since(state.last_cleanup) >= WINDOW {
+            state.windows.retain(|_, w| now.duration_since(w.start) < WINDOW);
+            state.last_cleanup = now;
+        }
+
+        let window = state.windows.entry(ip).or_insert(Window { start: now, count: 0 });
+        if now.duration_since(window.start) >= WINDOW {
+            *window = Window { start: now, count: 0 };
+        }
+        if window.count >= self.limit {
+            let remaining = WINDOW - now.duration_since(window.start);
+            return Err(remaining.as_secs().max(1));
+        }
+        window.count += 1;
+        Ok(())
+    }
+}
+
+
+#[derive(Debug)]
+pub struct TooManyRequests {
+    retry_after: u64
+}
+
+impl Reject for TooManyRequests {}
+
+
+/// This middleware rejects API requests from anonymous clients that went over
+/// the rate limit.
+/// 
+pub fn limit(limiter: Arc<RateLimiter>, store: Store) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::peek()
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>(KEY_HEADER))
+        .and_then(move |path: warp::path::Peek, addr: Option<SocketAddr>, key: Option<String>| {
+            let limiter = limiter.clone();
+            let store = store.clone();
+            async move {
+                // Only API routes are limited (not the scoreboard)
+                let is_api_route = path.segments().next() == Some("v1");
+                let is_authenticated = key.map(|k| store.keys.is_valid(&k)).unwrap_or(false);
+                if limiter.limit == 0 || !is_api_route || is_authenticated {
+                    return Ok(());
+                }
+                match addr {
+                    Some(addr) => limiter.hit(addr.ip())
+                        .map_err(|retry_after| warp::reject::custom(TooManyRequests { retry_after })),
+                    None => Ok(())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+
+/// Turns rate limit rejections into `429 Too Many Requests` responses.
+/// 
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<TooManyRequests>() {
+        Some(TooManyRequests { retry_after }) => Ok(warp::reply::with_header(
+            warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Too many requests."})),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            "retry-after",
+            retry_after.to_string(),
+        )),
+        None => Err(rejection)
+    }
+}
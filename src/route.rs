@@ -2,8 +2,9 @@ use std::collections::HashMap;
 use uuid::Uuid;
 use warp::{hyper::StatusCode, Filter, Reply, Rejection};
 
-use crate::Store;
+use crate::{Store, storage, keys::{self, Scope}, journal::Mutation, notifications::NotificationKind};
 use serde::{Serialize, Deserialize};
+use serde_json::json;
 
 
 pub type MapRoutes = HashMap<String, Vec<MapRoute>>;
@@ -94,7 +95,78 @@ pub struct MapRoute {
     robot: Robot,
     indicator: StartIndicator,
     route_name: RouteName,
-    entities: Option<Vec<MapObject>>
+    entities: Option<Vec<MapObject>>,
+    /// Runs faster than this (in seconds) are held for moderation
+    min_time: Option<f32>
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MapRoutePatch {
+    name: Option<String>,
+    start_line: Option<Line>,
+    finish_line: Option<Line>,
+    leaderboards: Option<Leaderboards>,
+    checkpoints: Option<Vec<[f64; 3]>>,
+    start: Option<StartPosition>,
+    end: Option<EndPosition>,
+    ziplines: Option<Vec<[[f64; 3]; 2]>>,
+    perks: Option<HashMap<String, String>>,
+    robot: Option<Robot>,
+    indicator: Option<StartIndicator>,
+    route_name: Option<RouteName>,
+    entities: Option<Vec<MapObject>>,
+    /// 0 removes the minimum time
+    min_time: Option<f32>
+}
+
+impl From<MapRoute> for MapRoutePatch {
+    fn from(route: MapRoute) -> Self {
+        MapRoutePatch {
+            name: Some(route.name),
+            start_line: Some(route.start_line),
+            finish_line: Some(route.finish_line),
+            leaderboards: Some(route.leaderboards),
+            checkpoints: Some(route.checkpoints),
+            start: Some(route.start),
+            end: Some(route.end),
+            ziplines: Some(route.ziplines),
+            perks: Some(route.perks.unwrap_or_default()),
+            robot: Some(route.robot),
+            indicator: Some(route.indicator),
+            route_name: Some(route.route_name),
+            entities: Some(route.entities.unwrap_or_default()),
+            min_time: Some(route.min_time.unwrap_or(0.0))
+        }
+    }
+}
+
+impl MapRoute {
+    pub fn checkpoint_count(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn min_time(&self) -> Option<f32> {
+        self.min_time
+    }
+
+    /// Overwrites route fields with those present in the patch.
+    /// 
+    fn apply(&mut self, patch: MapRoutePatch) {
+        if let Some(name) = patch.name { self.name = name; }
+        if let Some(start_line) = patch.start_line { self.start_line = start_line; }
+        if let Some(finish_line) = patch.finish_line { self.finish_line = finish_line; }
+        if let Some(leaderboards) = patch.leaderboards { self.leaderboards = leaderboards; }
+        if let Some(checkpoints) = patch.checkpoints { self.checkpoints = checkpoints; }
+        if let Some(start) = patch.start { self.start = start; }
+        if let Some(end) = patch.end { self.end = end; }
+        if let Some(ziplines) = patch.ziplines { self.ziplines = ziplines; }
+        if let Some(perks) = patch.perks { self.perks = Some(perks); }
+        if let Some(robot) = patch.robot { self.robot = robot; }
+        if let Some(indicator) = patch.indicator { self.indicator = indicator; }
+        if let Some(route_name) = patch.route_name { self.route_name = route_name; }
+        if let Some(entities) = patch.entities { self.entities = Some(entities); }
+        if let Some(min_time) = patch.min_time { self.min_time = if min_time > 0.0 { Some(min_time) } else { None }; }
+    }
 }
 
 
@@ -113,24 +185,24 @@ async fn create_map_route(
     store: Store
 ) -> Result<impl Reply, Rejection> {
 
-    // Check if provided map exists
-    let routes_list = store.routes_list.read().clone();
-    let map_routes = routes_list.get(&map_id);
-    if map_routes.is_none() {
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&"Map not found."),
+    // Routes list is held until the route is recorded, so that a concurrent
+    // map deletion either happens before (and the map isn't found) or after
+    // (and removes the new route)
+    let mut write_lock = store.routes_list.write();
+    let routes = match write_lock.get_mut(&map_id) {
+        Some(routes) => routes,
+        None => return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Map not found."})),
             StatusCode::NOT_FOUND,
         ))
-    }
+    };
 
-    let mut routes = map_routes.unwrap().clone();
-    let index = routes.iter().position(|route| route.name == entry.name).unwrap_or(usize::MAX);
-        if index != usize::MAX {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&"{\"error\": \"Route name already used.\"}"),
-                StatusCode::ALREADY_REPORTED,
-            ));
-        }
+    if routes.iter().any(|route| route.name == entry.name) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Route name already used."})),
+            StatusCode::ALREADY_REPORTED,
+        ));
+    }
 
     // Insert new route
     let route_id = Uuid::new_v4().to_string();
@@ -141,13 +213,12 @@ async fn create_map_route(
     if entry.entities.is_none() {
         entry.entities = Some(Vec::new());
     }
-    routes.push(entry);
-    let mut write_lock = store.routes_list.write();
-    write_lock.insert(map_id, routes);
+    routes.push(entry.clone());
 
     // Create associated scores
-    let mut scores_write_lock = store.scores_list.write();
-    scores_write_lock.insert(route_id, [].to_vec());
+    store.scores_list.write().insert(route_id.clone(), Vec::new());
+    store.notifier.publish(&route_id, NotificationKind::RouteCreated { map_id: map_id.clone(), name: entry.name.clone() });
+    store.storage.record(Mutation::PutRoute { map_id, route: Box::new(entry) });
 
     Ok(warp::reply::with_status(
         warp::reply::json(&"Map route created."),
@@ -166,7 +237,7 @@ async fn get_map_routes(
     let routes_read_lock = store.routes_list.read();
     if !routes_read_lock.contains_key(&map_id) {
         return Ok(warp::reply::with_status(
-            warp::reply::json(&"Route not found."),
+            warp::reply::json(&json!({"error": "Map not found."})),
             StatusCode::NOT_FOUND,
         ));
     }
@@ -179,11 +250,172 @@ async fn get_map_routes(
 }
 
 
+/// This middleware creates `MapRoutePatch` payloads from PATCH request bodies.
+/// 
+pub fn patch_json() -> impl Filter<Extract = (MapRoutePatch,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+
+/// Replaces all fields of an existing map route.
+/// 
+async fn replace_map_route(
+    map_id: String,
+    route_id: String,
+    entry: MapRoute,
+    store: Store
+) -> Result<impl Reply, Rejection> {
+    update_map_route(map_id, route_id, MapRoutePatch::from(entry), store).await
+}
+
+
+/// Updates fields of an existing map route; fields absent from the patch are
+/// left untouched.
+/// 
+async fn update_map_route(
+    map_id: String,
+    route_id: String,
+    patch: MapRoutePatch,
+    store: Store
+) -> Result<impl Reply, Rejection> {
+
+    let mut write_lock = store.routes_list.write();
+    let routes = match write_lock.get_mut(&map_id) {
+        Some(routes) => routes,
+        None => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Map not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+    let index = routes.iter().position(|r| r.id.as_deref() == Some(route_id.as_str())).unwrap_or(usize::MAX);
+    if index == usize::MAX {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Route not found."})),
+            StatusCode::NOT_FOUND,
+        ));
+    }
+
+    if let Some(name) = &patch.name {
+        if routes.iter().enumerate().any(|(i, r)| i != index && &r.name == name) {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Route name already used."})),
+                StatusCode::ALREADY_REPORTED,
+            ));
+        }
+    }
+    routes[index].apply(patch);
+    store.storage.record(Mutation::PutRoute { map_id, route: Box::new(routes[index].clone()) });
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&"Map route updated."),
+        StatusCode::OK,
+    ))
+}
+
+
+/// Deletes a map route, as well as its scores.
+/// 
+async fn delete_map_route(
+    map_id: String,
+    route_id: String,
+    store: Store
+) -> Result<impl Reply, Rejection> {
+
+    let mut write_lock = store.routes_list.write();
+    let routes = match write_lock.get_mut(&map_id) {
+        Some(routes) => routes,
+        None => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Map not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+    let index = routes.iter().position(|r| r.id.as_deref() == Some(route_id.as_str())).unwrap_or(usize::MAX);
+    if index == usize::MAX {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Route not found."})),
+            StatusCode::NOT_FOUND,
+        ));
+    }
+    routes.remove(index);
+    remove_route_scores(&store, &route_id);
+    store.storage.record(Mutation::DeleteRoute { map_id, route_id });
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&"Map route deleted."),
+        StatusCode::OK,
+    ))
+}
+
+
+/// Removes all routes associated to a map, cascading to their scores.
+/// 
+pub fn remove_map_routes(store: &Store, map_id: &str) {
+    let routes = store.routes_list.write().remove(map_id).unwrap_or_default();
+    for route in routes {
+        if let Some(route_id) = route.id {
+            remove_route_scores(store, &route_id);
+        }
+    }
+}
+
+
+/// Removes the leaderboard, run history and runs awaiting moderation of a
+/// route.
+/// 
+pub fn remove_route_scores(store: &Store, route_id: &str) {
+    store.scores_list.write().remove(route_id);
+    store.runs_list.write().remove(route_id);
+    store.flagged_runs.write().retain(|r| r.route_id != route_id);
+}
+
+
+/// Looks up a route across all maps, returning it along with the identifier of
+/// the map it belongs to.
+/// 
+pub fn find_route(store: &Store, route_id: &str) -> Option<(String, MapRoute)> {
+    let read_lock = store.routes_list.read();
+    read_lock.iter().find_map(|(map_id, routes)| {
+        routes.iter()
+            .find(|r| r.id.as_deref() == Some(route_id))
+            .map(|r| (map_id.clone(), r.clone()))
+    })
+}
+
+
+/// Get a single map route, based on its identifier.
+/// 
+async fn get_map_route(
+    route_id: String,
+    store: Store
+) -> Result<impl Reply, Rejection> {
+
+    match find_route(&store, &route_id) {
+        Some((_, route)) => Ok(warp::reply::with_status(
+            warp::reply::json(&route),
+            StatusCode::OK,
+        )),
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Route not found."})),
+            StatusCode::NOT_FOUND,
+        ))
+    }
+}
+
+
 /// Returns all map routing routes:
 ///     * one route to get a map's routes;
-///     * one route to create map routes.
+///     * one route to get a single route;
+///     * one route to create map routes;
+///     * one route to replace a map route;
+///     * one route to partially update a map route;
+///     * one route to delete a map route.
 /// 
 pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let write_filter = keys::authorize(store.clone(), Scope::RoutesWrite);
     let store_filter = warp::any().map(move || store.clone());
 
     let route_creation_route = warp::post()
@@ -192,9 +424,12 @@ pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error =
         .and(warp::path::param())
         .and(warp::path("routes"))
         .and(warp::path::end())
+        .and(write_filter.clone())
         .and(post_json())
         .and(store_filter.clone())
-        .and_then(create_map_route);
+        .and_then(create_map_route)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
 
     let get_routes_route = warp::get()
         .and(warp::path("v1"))
@@ -202,9 +437,63 @@ pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error =
         .and(warp::path::param())
         .and(warp::path("routes"))
         .and(warp::path::end())
-        .and(store_filter)
+        .and(store_filter.clone())
         .and_then(get_map_routes);
 
-    route_creation_route.or(get_routes_route)
+    let get_route_route = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(get_map_route);
+
+    let route_replacement_route = warp::put()
+        .and(warp::path("v1"))
+        .and(warp::path("maps"))
+        .and(warp::path::param())
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(post_json())
+        .and(store_filter.clone())
+        .and_then(replace_map_route)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let route_update_route = warp::patch()
+        .and(warp::path("v1"))
+        .and(warp::path("maps"))
+        .and(warp::path::param())
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(patch_json())
+        .and(store_filter.clone())
+        .and_then(update_map_route)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let route_deletion_route = warp::delete()
+        .and(warp::path("v1"))
+        .and(warp::path("maps"))
+        .and(warp::path::param())
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(store_filter.clone())
+        .and_then(delete_map_route)
+        .and(store_filter)
+        .and_then(storage::wait_commit);
+
+    route_creation_route
+        .or(get_routes_route)
+        .or(get_route_route)
+        .or(route_replacement_route)
+        .or(route_update_route)
+        .or(route_deletion_route)
 }
 
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use uuid::Uuid;
+use warp::{hyper::StatusCode, Filter, Reply, Rejection};
+
+use crate::{Store, journal::Mutation, notifications::NotificationKind, players, scores::{self, ScoreEntries, ScoreEntry}};
+
+pub type Runs = HashMap<String, Vec<Run>>;
+
+
+/// A run that was accepted on a route; all runs are kept (in submission
+/// order), leaderboards holding the best run of each player.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Run {
+    pub id: String,
+    #[serde(flatten)]
+    pub entry: ScoreEntry,
+    /// Submission timestamp (unknown for runs that were recorded before
+    /// history was kept)
+    pub submitted: Option<i64>,
+    /// Name of the API key the run was submitted with, which identifies the
+    /// game server
+    pub server: Option<String>
+}
+
+impl Run {
+    pub fn new(entry: ScoreEntry, submitted: i64, server: Option<String>) -> Self {
+        Run { id: Uuid::new_v4().to_string(), entry, submitted: Some(submitted), server }
+    }
+}
+
+
+/// A run, as listed in a player's progression.
+/// 
+#[derive(Serialize)]
+struct Progress<'a> {
+    #[serde(flatten)]
+    run: &'a Run,
+    /// Whether the run improved the player's best time
+    personal_best: bool
+}
+
+
+/// Adds a run to its route history, and updates the route leaderboard if it's
+/// the player's best run (notifying live subscribers); returns false if the
+/// player already had a better score.
+/// 
+/// The player of the run is registered first, so that the run is compared to
+/// the scores they submitted before having a UID.
+/// 
+pub fn record(store: &Store, route_id: &str, scores_list: &mut ScoreEntries, runs_list: &mut Runs, run: Run) -> bool {
+    players::register(store, scores_list, runs_list, &run.entry);
+
+    let entry = run.entry.clone();
+    runs_list.entry(route_id.to_string()).or_default().push(run.clone());
+    store.storage.record(Mutation::AddRun { route_id: route_id.to_string(), run });
+
+    let scores = scores_list.entry(route_id.to_string()).or_default();
+    let previous = scores.first().cloned();
+    if !scores::insert_best(scores, entry.clone()) {
+        return false;
+    }
+    store.storage.record(Mutation::PutScore { route_id: route_id.to_string(), player: entry.player_id().to_string(), entry: entry.clone() });
+
+    let rank = scores::rank(scores, entry.time);
+    store.notifier.publish(route_id, NotificationKind::ScoreCreated { entry: entry.clone(), rank });
+    if previous.as_ref().map_or(true, |p| entry.time < p.time) {
+        store.notifier.publish(route_id, NotificationKind::NewRecord { entry, previous });
+    }
+    true
+}
+
+
+/// Attributes runs of a player to the identity (name and UID) of their
+/// leaderboard entry, when it changes.
+/// 
+pub fn update_player(runs: &mut [Run], player: &str, entry: &ScoreEntry) {
+    for run in runs.iter_mut().filter(|r| r.entry.player_id() == player) {
+        run.entry.name = entry.name.clone();
+        run.entry.uid = entry.uid.clone();
+    }
+}
+
+
+/// Returns all runs of a player on a route, oldest first.
+/// 
+async fn get_player_runs(
+    route_id: String,
+    player: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let player = scores::decode_player_name(&player);
+        if !store.scores_list.read().contains_key(&route_id) {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Route not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+
+        let runs_list = store.runs_list.read();
+        let mut best = f32::INFINITY;
+        let progression: Vec<Progress> = runs_list.get(&route_id)
+            .into_iter()
+            .flatten()
+            .filter(|r| r.entry.player_id() == player)
+            .map(|run| {
+                let personal_best = run.entry.time < best;
+                best = best.min(run.entry.time);
+                Progress { run, personal_best }
+            })
+            .collect();
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&progression),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns all run-associated routes:
+///     * one route to list a player's runs on a route.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let store_filter = warp::any().map(move || store.clone());
+
+    warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path("players"))
+        .and(warp::path::param())
+        .and(warp::path("runs"))
+        .and(warp::path::end())
+        .and(store_filter)
+        .and_then(get_player_runs)
+}
@@ -1,14 +1,19 @@
-use std::{sync::Arc, time::SystemTime, fs::File, io::Read};
+use std::{sync::Arc, fs, path::{Path, PathBuf}, thread, time::{Duration, SystemTime}};
 
 use chrono::{NaiveDateTime, DateTime, Utc};
 use handlebars::{Handlebars, handlebars_helper};
+use parking_lot::RwLock;
 use serde::{Serialize, Deserialize};
 use serde_json::json;
-use warp::{Filter, Reply, Rejection};
+use warp::{hyper::StatusCode, Filter, Reply, Rejection};
 
-use crate::{Store, event::Event, log, scores::ScoreEntry};
+use crate::{Store, event::Event, leaderboard, log, scores::ScoreEntry};
 
-const TEMPLATE_FILE: &str = "scoreboard/template.html";
+const TEMPLATE_FILE: &str = "template.html";
+const MESSAGE_FILE: &str = "message.html";
+const PARTIALS_DIR: &str = "partials";
+const ASSETS_DIR: &str = "assets";
+const TEMPLATE_EXTENSIONS: [&str; 2] = ["html", "hbs"];
 
 struct WithTemplate<T: Serialize> {
     name: &'static str,
@@ -24,101 +29,217 @@ struct RouteResult {
 }
 
 
-fn render(hbs: Arc<Handlebars<'_>>, store: Store) -> impl warp::Reply
-{
-    // Find current event
-    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
-    let events = store.clone().events_list.read().clone().into_iter();
-    let corresponding_events: Vec<Event> = events.filter(|e| now >= e.start.try_into().unwrap() && now <= e.end.try_into().unwrap()).collect();
-    if corresponding_events.len() != 1 {
-        log::error(&format!("Expected one corresponding event, found {}.", corresponding_events.len()));
-        std::process::exit(42);
+/// Events displayed by the default scoreboard.
+/// 
+#[derive(Default)]
+struct DefaultEvent {
+    event: Option<Event>,
+    /// Other running events, which visitors can switch to
+    other_events: Vec<Event>,
+    /// Latest ended event, when an upcoming event is displayed
+    previous_event: Option<Event>
+}
+
+/// Picks the event displayed by default: the latest started one among running
+/// events, or else the next upcoming one, or else the latest ended one.
+/// 
+fn default_event(events: &[Event], now: i64) -> DefaultEvent {
+    let mut running: Vec<Event> = events.iter().filter(|e| e.start <= now && now <= e.end).cloned().collect();
+    running.sort_by_key(|e| std::cmp::Reverse(e.start));
+    if !running.is_empty() {
+        let event = running.remove(0);
+        return DefaultEvent { event: Some(event), other_events: running, previous_event: None };
     }
 
-    // Find associated maps
-    let event = corresponding_events.first().unwrap().clone();
-    let event_id = event.id.unwrap();
-    let maps = store.clone().maps_list.read().clone();
-    if !maps.contains_key(&event_id) {
-        log::error("Current event features no map.");
-        std::process::exit(1);
+    let ended = events.iter().filter(|e| e.end < now).max_by_key(|e| e.end).cloned();
+    match events.iter().filter(|e| e.start > now).min_by_key(|e| e.start) {
+        Some(upcoming) => DefaultEvent { event: Some(upcoming.clone()), other_events: Vec::new(), previous_event: ended },
+        None => DefaultEvent { event: ended, ..DefaultEvent::default() }
     }
-    let corresponding_maps = maps.get(&event_id).unwrap().clone();
-    let maps_clone = corresponding_maps.clone();
+}
 
-    // Build route objects
-    let mut results: Vec<RouteResult> = Vec::new();
-    for map in maps_clone {
-        let routes = store.clone().routes_list.read().clone();
-        let map_id = map.id.unwrap();
-        if !routes.contains_key(&map_id) {
-            log::warn(&format!("No route was found for map {}, skipping.", &map_id));
-            continue;
-        }
 
-        let corresponding_routes = routes.get(&map_id).unwrap().clone();
-        let mut map_routes: Vec<RouteResult> = corresponding_routes.into_iter().map(|route| {
-            return RouteResult {
-                id: route.id.unwrap(),
-                name: route.name,
-                map_name: map.map_name.clone(),
-                scores: Vec::new()
+/// Builds the template data of an event scoreboard: its routes along with
+/// their scores, and its overall ranking (events that haven't started yet are
+/// displayed as upcoming).
+/// 
+fn event_data(store: &Store, event: &Event, default: &DefaultEvent) -> serde_json::Value {
+    let event_id = event.id.clone().unwrap_or_default();
+    let maps = store.maps_list.read().get(&event_id).cloned().unwrap_or_default();
+
+    let mut results: Vec<RouteResult> = Vec::new();
+    {
+        let routes_list = store.routes_list.read();
+        let scores_list = store.scores_list.read();
+        for map in &maps {
+            let map_id = map.id.clone().unwrap_or_default();
+            for route in routes_list.get(&map_id).into_iter().flatten() {
+                let route_id = route.id.clone().unwrap_or_default();
+                results.push(RouteResult {
+                    scores: scores_list.get(&route_id).cloned().unwrap_or_default(),
+                    id: route_id,
+                    name: route.name.clone(),
+                    map_name: map.map_name.clone()
+                });
             }
-        }).collect();
-        results.append(&mut map_routes);
+        }
     }
 
-    // Load up scores in `results`
-    let scores = store.clone().scores_list.read().clone();
-    for result in &mut results {
-        let route_id = &result.id;
-        if !scores.contains_key(route_id) {
-            log::warn(&format!("No scores were found for route {}, skipping.", &route_id));
-            continue;
+    // Overall ranking across all routes
+    let scoring = event.scoring.clone().unwrap_or_default();
+    let score_label = scoring.label();
+    let overall = leaderboard::compute(store, event, scoring);
+
+    json!({
+        "event": event,
+        "upcoming": event.start > Utc::now().timestamp(),
+        "other_events": default.other_events,
+        "previous_event": default.previous_event,
+        "maps": maps,
+        "results": results,
+        "overall": overall,
+        "score_label": score_label
+    })
+}
+
+
+/// Renders the scoreboard of an event, or of the default event if none is
+/// requested; requests never fail, a page explaining what's missing is
+/// rendered instead.
+/// 
+fn render(templates: &Templates, store: Store, event_id: Option<String>) -> impl warp::Reply
+{
+    let events = store.events_list.read().clone();
+    let (data, status) = match event_id {
+        Some(event_id) => match events.iter().find(|e| e.id.as_deref() == Some(event_id.as_str())) {
+            Some(event) => (event_data(&store, event, &DefaultEvent::default()), StatusCode::OK),
+            None => (json!({"message": "This event does not exist."}), StatusCode::NOT_FOUND)
+        },
+        None => {
+            let default = default_event(&events, Utc::now().timestamp());
+            match &default.event {
+                Some(event) => (event_data(&store, event, &default), StatusCode::OK),
+                None => (json!({"message": "No event has been announced yet, come back later!"}), StatusCode::OK)
+            }
         }
-        result.scores = scores.get(route_id).unwrap().clone();
-    }
+    };
 
+    let hbs = templates.get();
+    // Templates are missing if they failed loading on startup, until they're
+    // reloaded
+    let (data, status) = if data.get("message").is_none() && !hbs.has_template(TEMPLATE_FILE) {
+        (json!({"message": "Scoreboard is unavailable, come back later!"}), StatusCode::SERVICE_UNAVAILABLE)
+    } else {
+        (data, status)
+    };
     let template = WithTemplate {
-        name: "template.html",
-        value: json!({
-            "event": corresponding_events.clone().first(),
-            "maps": corresponding_maps,
-            "results": results
-        }),
+        name: if data.get("message").is_some() { MESSAGE_FILE } else { TEMPLATE_FILE },
+        value: data,
     };
 
+    // Message template is optional, messages are displayed as is without it
+    if !hbs.has_template(template.name) {
+        let message = template.value["message"].as_str().unwrap_or_default().to_string();
+        return warp::reply::with_status(warp::reply::html(message), status);
+    }
+
     let render = hbs
         .render(template.name, &template.value)
-        .unwrap_or_else(|err| err.to_string());
-    
-    warp::reply::html(render)
+        .unwrap_or_else(|err| {
+            log::error(&format!("Failed rendering scoreboard [{}].", err));
+            err.to_string()
+        });
+
+    warp::reply::with_status(warp::reply::html(render), status)
 }
 
 
-pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    // Load HTML template
-    let mut file = match File::open(TEMPLATE_FILE) {
-        Ok(file) => file,
-        Err(_) => {
-            log::info(&format!("\"{}\" template file was not found.", TEMPLATE_FILE));
-            std::process::exit(3);
-        }
-    };
-    let mut data = String::new();
-    match file.read_to_string(&mut data) {
-        Ok(_) => (),
-        Err(err) => {
-            log::error(&format!("Failed reading \"{}\" file [{}].", TEMPLATE_FILE, err));
-            std::process::exit(2);
-        }
+/// Scoreboard templates and partials, which can be replaced while the API is
+/// running.
+/// 
+struct Templates {
+    dir: PathBuf,
+    registry: RwLock<Arc<Handlebars<'static>>>
+}
+
+impl Templates {
+    fn get(&self) -> Arc<Handlebars<'static>> {
+        self.registry.read().clone()
+    }
+}
+
+
+/// Lists template files: those at the root of the scoreboard directory, and
+/// those of its partials directory (which is optional).
+/// 
+fn template_files(dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), String> {
+    let list = |dir: &Path| -> Result<Vec<PathBuf>, String> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .map_err(|err| format!("failed reading \"{}\" directory [{}]", dir.display(), err))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file()
+                && path.extension().map_or(false, |ext| TEMPLATE_EXTENSIONS.iter().any(|e| ext == *e)))
+            .collect();
+        files.sort();
+        Ok(files)
     };
 
+    let templates = list(dir)?;
+    let partials_dir = dir.join(PARTIALS_DIR);
+    let partials = if partials_dir.is_dir() { list(&partials_dir)? } else { Vec::new() };
+    Ok((templates, partials))
+}
+
+
+/// Identifies the current version of template files, to detect changes.
+/// 
+fn fingerprint(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    let (templates, partials) = template_files(dir).unwrap_or_default();
+    templates.into_iter().chain(partials)
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+            let len = metadata.map(|m| m.len()).unwrap_or(0);
+            (path, modified, len)
+        })
+        .collect()
+}
+
+
+/// Builds a Handlebars registry holding all templates and partials of the
+/// scoreboard directory: templates are named after their file name, partials
+/// after their file stem.
+/// 
+fn load(dir: &Path) -> Result<Handlebars<'static>, String> {
+    let (templates, partials) = template_files(dir)?;
+    if !templates.iter().any(|path| path.file_name().map_or(false, |name| name == TEMPLATE_FILE)) {
+        return Err(format!("missing \"{}\" template file", dir.join(TEMPLATE_FILE).display()));
+    }
+
     let mut hb = Handlebars::new();
-    // register the template
-    hb.register_template_string("template.html", data)
-        .unwrap();
+    register_helpers(&mut hb);
+
+    for path in &templates {
+        let data = fs::read_to_string(path)
+            .map_err(|err| format!("failed reading \"{}\" template file [{}]", path.display(), err))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        hb.register_template_string(&name, data)
+            .map_err(|err| format!("invalid \"{}\" template [{}]", path.display(), err))?;
+    }
+
+    for path in &partials {
+        let data = fs::read_to_string(path)
+            .map_err(|err| format!("failed reading \"{}\" partial file [{}]", path.display(), err))?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        hb.register_partial(&name, data)
+            .map_err(|err| format!("invalid \"{}\" partial [{}]", path.display(), err))?;
+    }
+
+    Ok(hb)
+}
+
 
+fn register_helpers(hb: &mut Handlebars) {
     // Add a helper to have indexes starting from 1
     handlebars_helper!(score_index: |index: i64| index+1);
     hb.register_helper("score_index", Box::new(score_index));
@@ -127,28 +248,110 @@ pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error =
     handlebars_helper!(reddec: |time: f64| format!("{time:.3}"));
     hb.register_helper("reddec", Box::new(reddec));
 
+    // Add a helper to list sector times of a score entry
+    handlebars_helper!(sectors: |entry: ScoreEntry| {
+        entry.sectors().iter().map(|t| format!("{t:.3}")).collect::<Vec<String>>().join(" / ")
+    });
+    hb.register_helper("sectors", Box::new(sectors));
+
+    // Add a helper to display overall scores, which are whole numbers unless
+    // they're total times
+    handlebars_helper!(standing_score: |score: f64| {
+        if score.fract() == 0.0 { format!("{score:.0}") } else { format!("{score:.3}") }
+    });
+    hb.register_helper("standing_score", Box::new(standing_score));
+
     // Add a helper to format dates
     handlebars_helper!(date2: |timestamp: i64| {
-        let naive = NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap();
-        let datetime: DateTime<Utc> = DateTime::from_naive_utc_and_offset(naive, Utc);
-        let newdate = datetime.format("%Y-%m-%d %H:%M:%S");
-        newdate.to_string() + " UTC"
+        match NaiveDateTime::from_timestamp_opt(timestamp, 0) {
+            Some(naive) => {
+                let datetime: DateTime<Utc> = DateTime::from_naive_utc_and_offset(naive, Utc);
+                datetime.format("%Y-%m-%d %H:%M:%S").to_string() + " UTC"
+            },
+            None => String::from("unknown date")
+        }
     });
     hb.register_helper("date2", Box::new(date2));
+}
+
+
+/// Checks the scoreboard directory for template changes at a regular interval,
+/// reloading all templates when one is found; templates that fail loading are
+/// reported, and previous ones are kept.
+/// 
+fn start_reload_cron(templates: Arc<Templates>, seconds: u64, mut previous: Vec<(PathBuf, Option<SystemTime>, u64)>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(seconds));
+            let current = fingerprint(&templates.dir);
+            if current == previous {
+                continue;
+            }
+            previous = current;
+
+            match load(&templates.dir) {
+                Ok(hb) => {
+                    *templates.registry.write() = Arc::new(hb);
+                    log::info("Reloaded scoreboard templates.");
+                },
+                Err(err) => log::error(&format!("Failed reloading scoreboard templates [{}].", err))
+            }
+        }
+    });
+}
 
-    // Turn Handlebars instance into a Filter so we can combine it
-    // easily with others...
-    let hb = Arc::new(hb);
 
-    // Create a reusable closure to render template
-    let handlebars = move || render(hb.clone(), store.clone());
+/// Returns all scoreboard routes:
+///     * one route to serve CSS, JS and image assets;
+///     * one route to display the default event;
+///     * one route to display a given event.
+/// 
+/// Pages always reflect the current state of the store; an error is returned
+/// if templates can't be loaded. If `template_reload_seconds` isn't 0,
+/// templates are reloaded when they change (and routes are returned even if
+/// they can't be loaded yet, pages explaining that the scoreboard is
+/// unavailable until they are).
+/// 
+pub fn get_routes(store: Store, scoreboard_dir: &Path, template_reload_seconds: u64) -> Result<impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone, String> {
+    // Load HTML templates
+    let files = fingerprint(scoreboard_dir);
+    let registry = match load(scoreboard_dir) {
+        Ok(hb) => hb,
+        Err(err) if template_reload_seconds > 0 => {
+            log::error(&format!("Failed loading scoreboard templates, serving them once they're fixed [{}].", err));
+            Handlebars::new()
+        },
+        Err(err) => return Err(err)
+    };
+    let templates = Arc::new(Templates {
+        dir: scoreboard_dir.to_path_buf(),
+        registry: RwLock::new(Arc::new(registry))
+    });
+    if template_reload_seconds > 0 {
+        start_reload_cron(templates.clone(), template_reload_seconds, files);
+        log::info(&format!("Checking scoreboard templates for changes every {} seconds.", template_reload_seconds));
+    }
+
+    // Create reusable closures to render templates
+    let default_templates = templates.clone();
+    let default_store = store.clone();
+    let render_default = move || render(&default_templates, default_store.clone(), None);
+    let render_event = move |event_id: String| render(&templates, store.clone(), Some(event_id));
 
     // Static route to serve CSS and JS assets
-    let static_assets = warp::path("assets").and(warp::fs::dir("scoreboard/assets"));
+    let static_assets = warp::path("assets").and(warp::fs::dir(scoreboard_dir.join(ASSETS_DIR)));
 
     let get_scoreboard_route = warp::get()
         .and(warp::path::end())
-        .map(handlebars);
+        .map(render_default);
+
+    let get_event_scoreboard_route = warp::get()
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .map(render_event);
 
-    static_assets.or(get_scoreboard_route)
+    Ok(static_assets
+        .or(get_scoreboard_route)
+        .or(get_event_scoreboard_route))
 }
@@ -1,36 +1,164 @@
 use std::collections::HashMap;
 
+use chrono::Utc;
+use percent_encoding::percent_decode_str;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use warp::{hyper::StatusCode, Filter, Reply, Rejection};
 
-use crate::Store;
+use crate::{Store, storage, keys::{self, Scope}, journal::Mutation, log, moderation::{self, FlaggedRun}, players, route, runs::{self, Run}, signing};
 
 pub type ScoreEntries = HashMap<String, Vec<ScoreEntry>>;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ScoreEntry {
-    name: String,
-    time: f32,
+    /// Platform identifier of the player (entries submitted without one are
+    /// identified by their name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    pub name: String,
+    pub time: f32,
+    /// Time at which each route checkpoint was reached (in route order)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub splits: Option<Vec<f32>>,
 }
 
-/// Retrives scores list associated to a route id.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScoreEntryPatch {
+    name: Option<String>,
+    time: Option<f32>,
+    /// An empty list removes splits
+    splits: Option<Vec<f32>>,
+}
+
+impl ScoreEntry {
+    /// Identifies the player the entry belongs to.
+    /// 
+    pub fn player_id(&self) -> &str {
+        self.uid.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Checks that time is a positive number, and that splits (if any) match
+    /// the route checkpoints: there must be one per checkpoint, in increasing
+    /// order, and all before the final time (so that sector times add up to
+    /// it).
+    /// 
+    fn check(&self, checkpoint_count: usize) -> Result<(), String> {
+        if !self.time.is_finite() || self.time <= 0.0 {
+            return Err(String::from("time must be a positive number"));
+        }
+        let splits = match &self.splits {
+            Some(splits) => splits,
+            None => return Ok(())
+        };
+        if splits.len() != checkpoint_count {
+            return Err(format!("expected {} split(s), got {}", checkpoint_count, splits.len()));
+        }
+        let mut previous = 0.0;
+        for split in splits.iter().chain(std::iter::once(&self.time)) {
+            if !split.is_finite() || *split <= previous {
+                return Err(String::from("splits must be increasing and lower than final time"));
+            }
+            previous = *split;
+        }
+        Ok(())
+    }
+
+    /// Time spent between consecutive checkpoints (the last sector ending on
+    /// the finish line).
+    /// 
+    pub fn sectors(&self) -> Vec<f32> {
+        let splits = self.splits.clone().unwrap_or_default();
+        let mut previous = 0.0;
+        splits.iter().chain(std::iter::once(&self.time)).map(|split| {
+            let sector = split - previous;
+            previous = *split;
+            sector
+        }).collect()
+    }
+}
+
+/// Number of neighbours returned on each side of a player's entry, when
+/// listing scores around them.
+const DEFAULT_AROUND_LIMIT: usize = 5;
+
+/// Query parameters of score lists.
+/// 
+#[derive(Debug, Deserialize)]
+pub struct ScoresQuery {
+    /// Maximum number of entries to return (or number of neighbours on each
+    /// side of the player, with `around`)
+    limit: Option<usize>,
+    /// Number of entries to skip
+    offset: Option<usize>,
+    /// Player whose entry and neighbours are returned (`offset` is then
+    /// ignored)
+    around: Option<String>
+}
+
+/// A score entry, along with its rank on the leaderboard.
+/// 
+#[derive(Serialize)]
+struct RankedEntry<'a> {
+    rank: usize,
+    #[serde(flatten)]
+    entry: &'a ScoreEntry
+}
+
+/// Retrives scores list associated to a route id; the list can be paginated,
+/// or centered on a player.
 /// 
 async fn get_list(
     route_id: String,
+    query: ScoresQuery,
     store: Store
     ) -> Result<impl Reply, Rejection> {
 
     let scores_read_lock = store.scores_list.read();
-    if !scores_read_lock.contains_key(&route_id) {
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&"{\"message\": \"Route not found.\"}"),
+    let scores = match scores_read_lock.get(&route_id) {
+        Some(scores) => scores,
+        None => return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Route not found."})),
             StatusCode::NOT_FOUND,
-        ));
-    }
+        ))
+    };
+
+    let (start, end) = match query.around {
+        Some(player) => {
+            let index = match scores.iter().position(|e| e.player_id() == player) {
+                Some(index) => index,
+                None => return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": "Score not found."})),
+                    StatusCode::NOT_FOUND,
+                ))
+            };
+            let limit = query.limit.unwrap_or(DEFAULT_AROUND_LIMIT);
+            (index.saturating_sub(limit), index.saturating_add(limit).saturating_add(1))
+        },
+        None => {
+            let start = query.offset.unwrap_or(0);
+            (start, query.limit.map(|l| start.saturating_add(l)).unwrap_or(usize::MAX))
+        }
+    };
+    // Leaderboard is sorted, so entries share their predecessor's rank when
+    // they have the same time
+    let mut previous: Option<(f32, usize)> = None;
+    let page: Vec<RankedEntry> = scores.iter()
+        .enumerate()
+        .take(end)
+        .map(|(index, entry)| {
+            let rank = match previous {
+                Some((time, rank)) if time == entry.time => rank,
+                _ => index + 1
+            };
+            previous = Some((entry.time, rank));
+            RankedEntry { rank, entry }
+        })
+        .skip(start)
+        .collect();
 
-    let scores = scores_read_lock.get(&route_id).unwrap();
     Ok(warp::reply::with_status(
-        warp::reply::json(&scores),
+        warp::reply::json(&page),
         StatusCode::OK,
     ))
 }
@@ -41,63 +169,261 @@ fn post_json() -> impl Filter<Extract = (ScoreEntry,), Error = Rejection> + Clon
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
 
-/// Creates a score entry on a given route, based on its identifier.
+/// Records a run on a given route, based on its identifier; the run enters
+/// the route leaderboard if it's the player's best one.
 /// 
 async fn create_score_entry(
     route_id: String,
     entry: ScoreEntry,
+    server: Option<String>,
     store: Store
 ) -> Result<impl Reply, Rejection> {
 
     // Check if provided route exists
-    let scores_map: ScoreEntries = store.scores_list.read().clone();
-    let optional_scores = scores_map.get(&route_id);
-    if optional_scores.is_none() {
+    let route = match route::find_route(&store, &route_id) {
+        Some((_, route)) => route,
+        None => return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Route not found."})),
+            StatusCode::NOT_FOUND,
+        ))
+    };
+    if let Err(err) = entry.check(route.checkpoint_count()) {
         return Ok(warp::reply::with_status(
-            warp::reply::json(&"Route not found."),
+            warp::reply::json(&json!({"error": format!("Invalid score entry: {}.", err)})),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let mut write_lock = store.scores_list.write();
+    let scores = match write_lock.get(&route_id) {
+        Some(scores) => scores,
+        None => return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Route not found."})),
             StatusCode::NOT_FOUND,
         ))
+    };
+
+    // Suspicious runs are held out of history until reviewed
+    if let Some(reason) = moderation::check(&route, scores, &entry) {
+        log::warn(&format!("Holding run of {} on route {} for moderation ({}).", entry.name, route_id, reason));
+        let run = FlaggedRun::new(route_id, entry, reason, server);
+        store.flagged_runs.write().push(run.clone());
+        store.storage.record(Mutation::FlagRun { run });
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Score held for moderation."),
+            StatusCode::ACCEPTED,
+        ));
     }
 
-    let mut scores = optional_scores.unwrap().clone().to_vec();
-    let index = scores.iter().position(|e| e.name == entry.name).unwrap_or(usize::MAX);
-    if index != usize::MAX {
-        let existing_entry = &scores[index];
-        // If existing entry is better than new entry, we keep the new entry
-        if entry.time >= existing_entry.time {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&"{\"message\": \"Leaderboard contains a better score entry for this player.\"}"),
-                StatusCode::ALREADY_REPORTED,
-            ));
+    let run = Run::new(entry, Utc::now().timestamp(), server);
+    if !runs::record(&store, &route_id, &mut write_lock, &mut store.runs_list.write(), run) {
+        // Run is kept in history, but existing entry is better than new entry
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Leaderboard contains a better score entry for this player."})),
+            StatusCode::ALREADY_REPORTED,
+        ));
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&"Score created."),
+        StatusCode::CREATED,
+    ))
+}
+
+
+/// Inserts an entry in a (sorted) leaderboard, replacing the player's previous
+/// entry; returns false if the existing entry was at least as good, in which
+/// case the leaderboard is left untouched.
+/// 
+pub fn insert_best(scores: &mut Vec<ScoreEntry>, entry: ScoreEntry) -> bool {
+    if scores.iter().any(|e| e.player_id() == entry.player_id() && e.time <= entry.time) {
+        return false;
+    }
+    scores.retain(|e| e.player_id() != entry.player_id());
+    scores.push(entry);
+    sort(scores);
+    true
+}
+
+
+/// Position a time would have in a (sorted) leaderboard, starting at 1; equal
+/// times share the same rank.
+/// 
+pub fn rank(scores: &[ScoreEntry], time: f32) -> usize {
+    scores.iter().take_while(|e| e.time < time).count() + 1
+}
+
+
+/// Sorts a leaderboard by times.
+/// 
+pub fn sort(scores: &mut [ScoreEntry]) {
+    scores.sort_by(|a, b| a.time.total_cmp(&b.time));
+}
+
+
+/// This middleware creates `ScoreEntryPatch` payloads from PATCH request bodies.
+/// 
+fn patch_json() -> impl Filter<Extract = (ScoreEntryPatch,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+/// Players (identified by their UID, or by their name for entries submitted
+/// without one) are part of the URL, and thus arrive percent-encoded.
+/// 
+pub fn decode_player_name(player: &str) -> String {
+    percent_decode_str(player).decode_utf8_lossy().to_string()
+}
+
+/// Replaces a player's score entry on a given route.
+/// 
+async fn replace_score_entry(
+    route_id: String,
+    player: String,
+    entry: ScoreEntry,
+    store: Store
+) -> Result<impl Reply, Rejection> {
+    Ok(store_score_entry(route_id, player, store, |_| entry))
+}
+
+/// Updates a player's score entry on a given route; fields absent from the
+/// patch are left untouched.
+/// 
+async fn update_score_entry(
+    route_id: String,
+    player: String,
+    patch: ScoreEntryPatch,
+    store: Store
+) -> Result<impl Reply, Rejection> {
+    Ok(store_score_entry(route_id, player, store, |current| {
+        let mut entry = current.clone();
+        if let Some(name) = patch.name {
+            entry.name = name;
+        }
+        if let Some(time) = patch.time {
+            entry.time = time;
         }
-        // Else, we remove the existing entry
-        else {
-            scores.remove(index);
+        if let Some(splits) = patch.splits {
+            entry.splits = if splits.is_empty() { None } else { Some(splits) };
         }
+        entry
+    }))
+}
+
+/// Stores the entry built from a player's current score entry on a given
+/// route in its place, after checking it.
+/// 
+fn store_score_entry(
+    route_id: String,
+    player: String,
+    store: Store,
+    build: impl FnOnce(&ScoreEntry) -> ScoreEntry
+) -> warp::reply::WithStatus<warp::reply::Json> {
+
+    let player = decode_player_name(&player);
+    let checkpoint_count = route::find_route(&store, &route_id).map(|(_, r)| r.checkpoint_count()).unwrap_or(0);
+    let mut write_lock = store.scores_list.write();
+    let scores = match write_lock.get_mut(&route_id) {
+        Some(scores) => scores,
+        None => {
+            return warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Route not found."})),
+                StatusCode::NOT_FOUND,
+            );
+        }
+    };
+    let index = scores.iter().position(|e| e.player_id() == player).unwrap_or(usize::MAX);
+    if index == usize::MAX {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Score not found."})),
+            StatusCode::NOT_FOUND,
+        );
     }
 
-    // Create new entry
-    scores.push(ScoreEntry { name: entry.name, time: entry.time });
+    let entry = build(&scores[index]);
+    if let Err(err) = entry.check(checkpoint_count) {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({"error": format!("Invalid score entry: {}.", err)})),
+            StatusCode::BAD_REQUEST,
+        );
+    }
+    if scores.iter().enumerate().any(|(i, e)| i != index && e.player_id() == entry.player_id()) {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Leaderboard already contains a score entry for this player."})),
+            StatusCode::ALREADY_REPORTED,
+        );
+    }
+    let renamed = scores[index].name != entry.name;
+    let reassigned = scores[index].uid != entry.uid;
+    scores[index] = entry.clone();
 
-    // Sort list by times
-    scores.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    sort(scores);
+    let mut runs_list = store.runs_list.write();
+    if let Some(runs) = runs_list.get_mut(&route_id) {
+        runs::update_player(runs, &player, &entry);
+    }
+    store.storage.record(Mutation::PutScore { route_id, player, entry: entry.clone() });
 
-    // Restore list
+    // Names of registered players are shared by all their entries
+    if let (Some(uid), true) = (&entry.uid, renamed || reassigned) {
+        players::set_name(&store, &mut write_lock, &mut runs_list, uid, &entry.name);
+    }
+
+    warp::reply::with_status(
+        warp::reply::json(&"Score updated."),
+        StatusCode::OK,
+    )
+}
+
+/// Deletes a player's score entry from a given route, along with their run
+/// history.
+/// 
+async fn delete_score_entry(
+    route_id: String,
+    player: String,
+    store: Store
+) -> Result<impl Reply, Rejection> {
+
+    let player = decode_player_name(&player);
     let mut write_lock = store.scores_list.write();
-    write_lock.insert(route_id, scores.to_vec());
+    let scores = match write_lock.get_mut(&route_id) {
+        Some(scores) => scores,
+        None => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Route not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+    let index = scores.iter().position(|e| e.player_id() == player).unwrap_or(usize::MAX);
+    if index == usize::MAX {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Score not found."})),
+            StatusCode::NOT_FOUND,
+        ));
+    }
+    scores.remove(index);
+    if let Some(runs) = store.runs_list.write().get_mut(&route_id) {
+        runs.retain(|r| r.entry.player_id() != player);
+    }
+    store.storage.record(Mutation::DeleteScore { route_id, player });
 
     Ok(warp::reply::with_status(
-        warp::reply::json(&"Score created."),
-        StatusCode::CREATED,
+        warp::reply::json(&"Score deleted."),
+        StatusCode::OK,
     ))
 }
 
 
 /// Returns all score-associated routes:
 ///     * one route to list a route's scores;
-///     * one route to create scores on a given route.
+///     * one route to create scores on a given route;
+///     * one route to replace a player's score;
+///     * one route to partially update a player's score;
+///     * one route to delete a player's score.
 /// 
 pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let write_filter = keys::authorize(store.clone(), Scope::ScoresWrite);
+    let signed_body = signing::signed_body(store.clone());
+    let key_name = keys::key_name(store.clone());
     let store_filter = warp::any().map(move || store.clone());
 
     let scores_list_route = warp::get()
@@ -106,6 +432,7 @@ pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error =
         .and(warp::path::param())
         .and(warp::path("scores"))
         .and(warp::path::end())
+        .and(warp::query::<ScoresQuery>())
         .and(store_filter.clone())
         .and_then(get_list);
 
@@ -116,9 +443,60 @@ pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error =
         .and(warp::path::param())
         .and(warp::path("scores"))
         .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(signed_body)
+        .and_then(signing::verify_score)
+        .untuple_one()
+        .and(key_name)
+        .and(store_filter.clone())
+        .and_then(create_score_entry)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let score_replacement_route = warp::put()
+        .and(warp::path("v1"))
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path("scores"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
         .and(post_json())
+        .and(store_filter.clone())
+        .and_then(replace_score_entry)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let score_update_route = warp::patch()
+        .and(warp::path("v1"))
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path("scores"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(patch_json())
+        .and(store_filter.clone())
+        .and_then(update_score_entry)
+        .and(store_filter.clone())
+        .and_then(storage::wait_commit);
+
+    let score_deletion_route = warp::delete()
+        .and(warp::path("v1"))
+        .and(warp::path("routes"))
+        .and(warp::path::param())
+        .and(warp::path("scores"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(write_filter.clone())
+        .and(store_filter.clone())
+        .and_then(delete_score_entry)
         .and(store_filter)
-        .and_then(create_score_entry);
+        .and_then(storage::wait_commit);
 
-    scores_list_route.or(score_creation_route)
+    scores_list_route
+        .or(score_creation_route)
+        .or(score_replacement_route)
+        .or(score_update_route)
+        .or(score_deletion_route)
 }
@@ -0,0 +1,135 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use warp::{hyper::body::Bytes, hyper::StatusCode, reject::Reject, Filter, Reply, Rejection};
+
+use crate::{Store, keys::KEY_HEADER, scores::ScoreEntry};
+
+const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+const NONCE_HEADER: &str = "x-signature-nonce";
+const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Maximum difference (in seconds) between a signed request timestamp and
+/// server time; nonces are remembered for that long.
+const SIGNATURE_WINDOW: i64 = 300;
+
+
+/// Reasons why a signed request was refused.
+/// 
+#[derive(Debug)]
+pub enum SignatureRejection {
+    /// Key requires signed requests, but some signature headers are missing
+    Missing,
+    /// Key has no signing secret (root key, or key issued before signatures
+    /// were introduced)
+    Unsupported,
+    /// Timestamp is malformed or outside the signature window
+    Expired,
+    /// Nonce was already used
+    Replayed,
+    /// Signature doesn't match request content
+    Invalid,
+    /// Body is not a valid payload
+    MalformedBody(String)
+}
+
+impl Reject for SignatureRejection {}
+
+
+/// A request body, along with the headers needed to verify its signature.
+/// 
+pub struct SignedBody {
+    key: Option<String>,
+    timestamp: Option<String>,
+    nonce: Option<String>,
+    signature: Option<String>,
+    body: Bytes,
+    store: Store
+}
+
+
+/// This middleware collects the body and signature headers of a request.
+/// 
+pub fn signed_body(store: Store) -> impl Filter<Extract = (SignedBody,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(KEY_HEADER)
+        .and(warp::header::optional::<String>(TIMESTAMP_HEADER))
+        .and(warp::header::optional::<String>(NONCE_HEADER))
+        .and(warp::header::optional::<String>(SIGNATURE_HEADER))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::bytes())
+        .map(move |key, timestamp, nonce, signature, body| SignedBody {
+            key, timestamp, nonce, signature, body, store: store.clone()
+        })
+}
+
+
+/// Verifies the signature of a score submission (if the request is signed, or
+/// if its key requires it), and deserializes the score entry it holds.
+/// 
+/// The signature is the hex-encoded HMAC-SHA256 (keyed with the key signing
+/// secret) of the timestamp, nonce, route identifier and body, separated by
+/// newlines.
+/// 
+pub async fn verify_score(
+    route_id: String,
+    request: SignedBody
+    ) -> Result<(String, ScoreEntry), Rejection> {
+        verify(&route_id, &request).map_err(warp::reject::custom)?;
+        let entry: ScoreEntry = serde_json::from_slice(&request.body)
+            .map_err(|err| warp::reject::custom(SignatureRejection::MalformedBody(err.to_string())))?;
+        Ok((route_id, entry))
+}
+
+
+fn verify(route_id: &str, request: &SignedBody) -> Result<(), SignatureRejection> {
+    // Keys were checked by the scope filter already
+    let signer = match request.key.as_deref().and_then(|k| request.store.keys.signer(k)) {
+        Some(signer) => signer,
+        None => return Ok(())
+    };
+
+    let (timestamp, nonce, signature) = match (&request.timestamp, &request.nonce, &request.signature) {
+        (Some(timestamp), Some(nonce), Some(signature)) => (timestamp, nonce, signature),
+        (None, None, None) if !signer.signed => return Ok(()),
+        _ => return Err(SignatureRejection::Missing)
+    };
+    let secret = signer.signing_secret.ok_or(SignatureRejection::Unsupported)?;
+
+    let time: i64 = timestamp.parse().map_err(|_| SignatureRejection::Expired)?;
+    if (Utc::now().timestamp() - time).abs() > SIGNATURE_WINDOW {
+        return Err(SignatureRejection::Expired);
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|_| SignatureRejection::Invalid)?;
+    mac.update(format!("{}\n{}\n{}\n", timestamp, nonce, route_id).as_bytes());
+    mac.update(&request.body);
+    let signature = hex::decode(signature).map_err(|_| SignatureRejection::Invalid)?;
+    mac.verify_slice(&signature).map_err(|_| SignatureRejection::Invalid)?;
+
+    // Only remember nonces of valid requests, so they can't be burnt by others
+    if !request.store.keys.register_nonce(&signer.key_id, nonce, time, SIGNATURE_WINDOW) {
+        return Err(SignatureRejection::Replayed);
+    }
+    Ok(())
+}
+
+
+/// Turns signature rejections into error responses.
+/// 
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    let (message, status) = match rejection.find::<SignatureRejection>() {
+        Some(SignatureRejection::Missing) => (String::from("Request must be signed."), StatusCode::UNAUTHORIZED),
+        Some(SignatureRejection::Unsupported) => (String::from("API key can't sign requests."), StatusCode::UNAUTHORIZED),
+        Some(SignatureRejection::Expired) => (String::from("Signature timestamp is invalid or expired."), StatusCode::UNAUTHORIZED),
+        Some(SignatureRejection::Replayed) => (String::from("Signature nonce was already used."), StatusCode::UNAUTHORIZED),
+        Some(SignatureRejection::Invalid) => (String::from("Invalid signature."), StatusCode::UNAUTHORIZED),
+        Some(SignatureRejection::MalformedBody(err)) => (format!("Request body deserialize error: {}.", err), StatusCode::BAD_REQUEST),
+        None => return Err(rejection)
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"error": message})),
+        status,
+    ))
+}
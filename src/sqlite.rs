@@ -0,0 +1,562 @@
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::event::Event;
+use crate::journal::Mutation;
+use crate::map::Map;
+use crate::moderation::FlaggedRun;
+use crate::players::Player;
+use crate::persistence;
+use crate::route::MapRoute;
+use crate::runs::Run;
+use crate::scores::ScoreEntry;
+use crate::storage::{Commit, Storage, WriteQueue};
+use crate::{Store, log};
+
+const DATABASE_FILE: &str = "parkour.db";
+
+/// Key of the `meta` row recording that state stored in JSON files was
+/// imported (which only happens once, on first start).
+const JSON_IMPORT_KEY: &str = "json_import";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        start_time INTEGER NOT NULL,
+        end_time INTEGER NOT NULL,
+        scoring TEXT
+    );
+    CREATE TABLE IF NOT EXISTS maps (
+        id TEXT PRIMARY KEY,
+        event_id TEXT NOT NULL,
+        map_name TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS maps_event_id ON maps (event_id);
+    CREATE TABLE IF NOT EXISTS routes (
+        id TEXT PRIMARY KEY,
+        map_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS routes_map_id ON routes (map_id);
+    CREATE TABLE IF NOT EXISTS scores (
+        route_id TEXT NOT NULL,
+        player_id TEXT NOT NULL,
+        player TEXT NOT NULL,
+        uid TEXT,
+        time REAL NOT NULL,
+        splits TEXT,
+        PRIMARY KEY (route_id, player_id)
+    );
+    CREATE TABLE IF NOT EXISTS runs (
+        id TEXT PRIMARY KEY,
+        route_id TEXT NOT NULL,
+        player TEXT NOT NULL,
+        uid TEXT,
+        time REAL NOT NULL,
+        splits TEXT,
+        submitted INTEGER,
+        server TEXT
+    );
+    CREATE INDEX IF NOT EXISTS runs_route_id_player ON runs (route_id, player);
+    CREATE TABLE IF NOT EXISTS players (
+        uid TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS flagged_runs (
+        id TEXT PRIMARY KEY,
+        route_id TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+
+/// Stores state in an embedded SQLite database; each mutation is written as
+/// it happens (by a writer thread, which writes mutations made concurrently in
+/// a single transaction), so saving state only consists in checkpointing the
+/// database.
+/// 
+pub struct SqliteStorage {
+    data_dir: PathBuf,
+    connection: Arc<Mutex<Connection>>,
+    queue: WriteQueue<Mutation>
+}
+
+impl SqliteStorage {
+    pub fn open(data_dir: &Path) -> Result<Self, String> {
+        create_dir_all(data_dir)
+            .map_err(|err| format!("failed creating data directory [{}]", err))?;
+        let path = data_dir.join(DATABASE_FILE);
+        let connection = Connection::open(&path)
+            .map_err(|err| format!("failed opening \"{}\" [{}]", path.display(), err))?;
+        connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL;")
+            .and_then(|_| connection.execute_batch(SCHEMA))
+            .and_then(|_| upgrade_schema(&connection))
+            .map_err(|err| format!("failed initializing \"{}\" [{}]", path.display(), err))?;
+        let connection = Arc::new(Mutex::new(connection));
+        let writer_connection = connection.clone();
+        Ok(SqliteStorage {
+            data_dir: data_dir.to_path_buf(),
+            connection,
+            queue: WriteQueue::start(move |batch| write_mutations(&writer_connection, batch))
+        })
+    }
+
+    /// Replaces store state with database content.
+    /// 
+    fn load_tables(&self, store: &Store) -> rusqlite::Result<()> {
+        let connection = self.connection.lock();
+
+        let mut statement = connection.prepare("SELECT id, name, description, start_time, end_time, scoring FROM events ORDER BY rowid")?;
+        let events = statement.query_map([], |row| Ok(Event {
+            id: Some(row.get(0)?),
+            name: row.get(1)?,
+            description: row.get(2)?,
+            start: row.get(3)?,
+            end: row.get(4)?,
+            scoring: read_json(row, 5)?
+        }))?.collect::<rusqlite::Result<Vec<Event>>>()?;
+        let event_ids: Vec<String> = events.iter().filter_map(|e| e.id.clone()).collect();
+        *store.events_list.write() = events;
+        let mut maps_list = store.maps_list.write();
+        maps_list.clear();
+        for event_id in event_ids {
+            maps_list.insert(event_id, Vec::new());
+        }
+
+        let mut statement = connection.prepare("SELECT event_id, id, map_name FROM maps ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, Map {
+            id: Some(row.get(1)?),
+            map_name: row.get(2)?
+        })))?;
+        let mut routes_list = store.routes_list.write();
+        routes_list.clear();
+        for row in rows {
+            let (event_id, map) = row?;
+            routes_list.insert(map.id.clone().unwrap(), Vec::new());
+            maps_list.entry(event_id).or_default().push(map);
+        }
+
+        let mut statement = connection.prepare("SELECT map_id, data FROM routes ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        let mut scores_list = store.scores_list.write();
+        scores_list.clear();
+        for row in rows {
+            let (map_id, data) = row?;
+            let route: MapRoute = serde_json::from_str(&data)
+                .map_err(|err| rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(err)))?;
+            scores_list.insert(route.id.clone().unwrap(), Vec::new());
+            routes_list.entry(map_id).or_default().push(route);
+        }
+
+        let mut statement = connection.prepare("SELECT route_id, uid, player, time, splits FROM scores ORDER BY route_id, time")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, ScoreEntry {
+            uid: row.get(1)?,
+            name: row.get(2)?,
+            time: row.get(3)?,
+            splits: read_json(row, 4)?
+        })))?;
+        for row in rows {
+            let (route_id, entry) = row?;
+            scores_list.entry(route_id).or_default().push(entry);
+        }
+
+        let mut statement = connection.prepare("SELECT route_id, id, uid, player, time, splits, submitted, server FROM runs ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, Run {
+            id: row.get(1)?,
+            entry: ScoreEntry {
+                uid: row.get(2)?,
+                name: row.get(3)?,
+                time: row.get(4)?,
+                splits: read_json(row, 5)?
+            },
+            submitted: row.get(6)?,
+            server: row.get(7)?
+        })))?;
+        let mut runs_list = store.runs_list.write();
+        runs_list.clear();
+        for row in rows {
+            let (route_id, run) = row?;
+            runs_list.entry(route_id).or_default().push(run);
+        }
+
+        let mut statement = connection.prepare("SELECT data FROM players")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut players = store.players_list.write();
+        players.clear();
+        for row in rows {
+            let player: Player = serde_json::from_str(&row?)
+                .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err)))?;
+            players.insert(player.uid.clone(), player);
+        }
+
+        let mut statement = connection.prepare("SELECT data FROM flagged_runs ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut flagged_runs = store.flagged_runs.write();
+        flagged_runs.clear();
+        for row in rows {
+            let run: FlaggedRun = serde_json::from_str(&row?)
+                .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err)))?;
+            flagged_runs.push(run);
+        }
+
+        Ok(())
+    }
+
+    /// Whether state stored in JSON files was already imported.
+    /// 
+    fn is_imported(&self) -> rusqlite::Result<bool> {
+        let connection = self.connection.lock();
+        connection.query_row("SELECT value FROM meta WHERE key = ?1", params![JSON_IMPORT_KEY], |row| row.get::<_, String>(0))
+            .optional()
+            .map(|value| value.is_some())
+    }
+
+    /// Fills the database with state stored in JSON files, if any, and records
+    /// that it did so; JSON files are left untouched.
+    /// 
+    fn import_json_state(&self, store: &Store) -> Result<(), String> {
+        persistence::read_state(store, &self.data_dir)?;
+
+        let mut mutations: Vec<Mutation> = Vec::new();
+        for event in store.events_list.read().iter() {
+            mutations.push(Mutation::PutEvent { event: event.clone() });
+        }
+        for (event_id, maps) in store.maps_list.read().iter() {
+            for map in maps {
+                mutations.push(Mutation::PutMap { event_id: event_id.clone(), map: map.clone() });
+            }
+        }
+        for (map_id, routes) in store.routes_list.read().iter() {
+            for route in routes {
+                mutations.push(Mutation::PutRoute { map_id: map_id.clone(), route: Box::new(route.clone()) });
+            }
+        }
+        for (route_id, scores) in store.scores_list.read().iter() {
+            for entry in scores {
+                mutations.push(Mutation::PutScore { route_id: route_id.clone(), player: entry.player_id().to_string(), entry: entry.clone() });
+            }
+        }
+        for (route_id, runs) in store.runs_list.read().iter() {
+            for run in runs {
+                mutations.push(Mutation::AddRun { route_id: route_id.clone(), run: run.clone() });
+            }
+        }
+        for player in store.players_list.read().values() {
+            mutations.push(Mutation::PutPlayer { player: player.clone() });
+        }
+        for run in store.flagged_runs.read().iter() {
+            mutations.push(Mutation::FlagRun { run: run.clone() });
+        }
+
+        let mut connection = self.connection.lock();
+        let transaction = connection.transaction()
+            .map_err(|err| format!("failed starting transaction [{}]", err))?;
+        for mutation in &mutations {
+            apply(&transaction, mutation)
+                .map_err(|err| format!("failed importing JSON state [{}]", err))?;
+        }
+        transaction.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+            params![JSON_IMPORT_KEY, Utc::now().timestamp().to_string()]
+        ).map_err(|err| format!("failed recording JSON state import [{}]", err))?;
+        transaction.commit()
+            .map_err(|err| format!("failed committing JSON state import [{}]", err))?;
+        if !mutations.is_empty() {
+            log::info(&format!("Imported {} entities from JSON files into SQLite database.", mutations.len()));
+        }
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self, store: &Store) -> Result<(), String> {
+        let is_imported = self.is_imported()
+            .map_err(|err| format!("failed reading SQLite database metadata [{}]", err))?;
+        if !is_imported {
+            self.import_json_state(store)?;
+        }
+
+        self.load_tables(store)
+            .map_err(|err| format!("failed loading SQLite database [{}]", err))?;
+        log::info("Loaded state from SQLite database.");
+        Ok(())
+    }
+
+    fn record(&self, mutation: Mutation) {
+        self.queue.push(mutation);
+    }
+
+    fn commit(&self) -> Commit {
+        self.queue.commit()
+    }
+
+    fn save(&self, _store: &Store) -> Result<(), String> {
+        let connection = self.connection.lock();
+        connection.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
+            .map_err(|err| format!("failed checkpointing SQLite database [{}]", err))?;
+        log::info("Checkpointed SQLite database.");
+        Ok(())
+    }
+}
+
+
+/// Adds columns that were introduced after the database was created.
+/// 
+fn upgrade_schema(connection: &Connection) -> rusqlite::Result<()> {
+    if !table_columns(connection, "events")?.iter().any(|c| c == "scoring") {
+        connection.execute_batch("ALTER TABLE events ADD COLUMN scoring TEXT;")?;
+        log::info("Added scoring column to SQLite events table.");
+    }
+
+    let columns = table_columns(connection, "scores")?;
+    if !columns.iter().any(|c| c == "splits") {
+        connection.execute_batch("ALTER TABLE scores ADD COLUMN splits TEXT;")?;
+        log::info("Added splits column to SQLite scores table.");
+    }
+
+    // Scores used to be keyed by player name; the table is rebuilt to be keyed
+    // by player identifier (UID, or name for scores submitted without one)
+    if !columns.iter().any(|c| c == "player_id") {
+        connection.execute_batch("
+            BEGIN;
+            ALTER TABLE scores RENAME TO scores_by_name;
+            CREATE TABLE scores (
+                route_id TEXT NOT NULL,
+                player_id TEXT NOT NULL,
+                player TEXT NOT NULL,
+                uid TEXT,
+                time REAL NOT NULL,
+                splits TEXT,
+                PRIMARY KEY (route_id, player_id)
+            );
+            INSERT INTO scores (route_id, player_id, player, time, splits)
+                SELECT route_id, player, player, time, splits FROM scores_by_name;
+            DROP TABLE scores_by_name;
+            COMMIT;
+        ")?;
+        log::info("Keyed SQLite scores table by player identifier.");
+    }
+    if !table_columns(connection, "runs")?.iter().any(|c| c == "uid") {
+        connection.execute_batch("ALTER TABLE runs ADD COLUMN uid TEXT;")?;
+        log::info("Added uid column to SQLite runs table.");
+    }
+
+    // Databases created before the import was recorded only imported JSON
+    // state if they were empty on first start
+    let has_events = connection.query_row("SELECT EXISTS (SELECT 1 FROM events)", [], |row| row.get::<_, bool>(0))?;
+    if has_events {
+        connection.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO NOTHING",
+            params![JSON_IMPORT_KEY, Utc::now().timestamp().to_string()]
+        )?;
+    }
+
+    // Run history starts with scores of databases created before it was kept
+    let has_runs = connection.query_row("SELECT EXISTS (SELECT 1 FROM runs)", [], |row| row.get::<_, bool>(0))?;
+    if !has_runs {
+        let count = connection.execute(
+            "INSERT INTO runs (id, route_id, player, uid, time, splits)
+                SELECT lower(hex(randomblob(16))), route_id, player, uid, time, splits FROM scores",
+            []
+        )?;
+        if count > 0 {
+            log::info(&format!("Added {} existing score(s) to SQLite runs table.", count));
+        }
+    }
+    Ok(())
+}
+
+
+fn table_columns(connection: &Connection, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut statement = connection.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+    let columns = statement.query_map([], |row| row.get::<_, String>(0))?.collect();
+    columns
+}
+
+
+/// Reads an optional value stored as JSON text (such as score splits).
+/// 
+fn read_json<T: DeserializeOwned>(row: &rusqlite::Row, index: usize) -> rusqlite::Result<Option<T>> {
+    match row.get::<_, Option<String>>(index)? {
+        Some(data) => serde_json::from_str(&data)
+            .map(Some)
+            .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(err))),
+        None => Ok(None)
+    }
+}
+
+
+fn write_json<T: Serialize>(value: &Option<T>) -> rusqlite::Result<Option<String>> {
+    match value {
+        Some(value) => serde_json::to_string(value)
+            .map(Some)
+            .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err))),
+        None => Ok(None)
+    }
+}
+
+
+/// Writes a batch of mutations in a single transaction; a mutation that can't
+/// be written is reported, without preventing the others from being written.
+/// 
+fn write_mutations(connection: &Mutex<Connection>, batch: Vec<(u64, Mutation)>) {
+    let mut connection = connection.lock();
+    let result = connection.transaction().and_then(|mut transaction| {
+        for (_, mutation) in &batch {
+            let savepoint = transaction.savepoint()?;
+            match apply(&savepoint, mutation) {
+                Ok(_) => savepoint.commit()?,
+                // Dropping the savepoint rolls the mutation back
+                Err(err) => log::error(&format!("Failed writing mutation to SQLite database [{}].", err))
+            }
+        }
+        transaction.commit()
+    });
+    if let Err(err) = result {
+        log::error(&format!("Failed writing {} mutation(s) to SQLite database [{}].", batch.len(), err));
+    }
+}
+
+
+/// Writes a mutation to the database; deletions cascade like they do in the
+/// store.
+/// 
+fn apply(transaction: &Connection, mutation: &Mutation) -> rusqlite::Result<()> {
+    match mutation {
+        Mutation::PutEvent { event } => {
+            transaction.execute(
+                "INSERT INTO events (id, name, description, start_time, end_time, scoring) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    ON CONFLICT (id) DO UPDATE SET name = ?2, description = ?3, start_time = ?4, end_time = ?5, scoring = ?6",
+                params![event.id, event.name, event.description, event.start, event.end, write_json(&event.scoring)?]
+            )?;
+        },
+        Mutation::DeleteEvent { event_id } => {
+            for table in ["scores", "runs", "flagged_runs"] {
+                transaction.execute(
+                    &format!("DELETE FROM {} WHERE route_id IN (SELECT routes.id FROM routes JOIN maps ON routes.map_id = maps.id WHERE maps.event_id = ?1)", table),
+                    params![event_id]
+                )?;
+            }
+            transaction.execute(
+                "DELETE FROM routes WHERE map_id IN (SELECT id FROM maps WHERE event_id = ?1)",
+                params![event_id]
+            )?;
+            transaction.execute("DELETE FROM maps WHERE event_id = ?1", params![event_id])?;
+            transaction.execute("DELETE FROM events WHERE id = ?1", params![event_id])?;
+        },
+        Mutation::PutMap { event_id, map } => {
+            transaction.execute(
+                "INSERT INTO maps (id, event_id, map_name) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (id) DO UPDATE SET event_id = ?2, map_name = ?3",
+                params![map.id, event_id, map.map_name]
+            )?;
+        },
+        Mutation::DeleteMap { event_id: _, map_id } => {
+            for table in ["scores", "runs", "flagged_runs"] {
+                transaction.execute(
+                    &format!("DELETE FROM {} WHERE route_id IN (SELECT id FROM routes WHERE map_id = ?1)", table),
+                    params![map_id]
+                )?;
+            }
+            transaction.execute("DELETE FROM routes WHERE map_id = ?1", params![map_id])?;
+            transaction.execute("DELETE FROM maps WHERE id = ?1", params![map_id])?;
+        },
+        Mutation::PutRoute { map_id, route } => {
+            let data = serde_json::to_string(route)
+                .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+            transaction.execute(
+                "INSERT INTO routes (id, map_id, name, data) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (id) DO UPDATE SET map_id = ?2, name = ?3, data = ?4",
+                params![route.id, map_id, route.name, data]
+            )?;
+        },
+        Mutation::DeleteRoute { map_id: _, route_id } => {
+            transaction.execute("DELETE FROM scores WHERE route_id = ?1", params![route_id])?;
+            transaction.execute("DELETE FROM runs WHERE route_id = ?1", params![route_id])?;
+            transaction.execute("DELETE FROM flagged_runs WHERE route_id = ?1", params![route_id])?;
+            transaction.execute("DELETE FROM routes WHERE id = ?1", params![route_id])?;
+        },
+        Mutation::PutScore { route_id, player, entry } => {
+            transaction.execute(
+                "DELETE FROM scores WHERE route_id = ?1 AND (player_id = ?2 OR player_id = ?3)",
+                params![route_id, player, entry.player_id()]
+            )?;
+            transaction.execute(
+                "INSERT INTO scores (route_id, player_id, player, uid, time, splits) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![route_id, entry.player_id(), entry.name, entry.uid, entry.time, write_json(&entry.splits)?]
+            )?;
+            transaction.execute(
+                "UPDATE runs SET player = ?3, uid = ?4 WHERE route_id = ?1 AND COALESCE(uid, player) = ?2",
+                params![route_id, player, entry.name, entry.uid]
+            )?;
+        },
+        Mutation::DeleteScore { route_id, player } => {
+            transaction.execute(
+                "DELETE FROM scores WHERE route_id = ?1 AND player_id = ?2",
+                params![route_id, player]
+            )?;
+            transaction.execute(
+                "DELETE FROM runs WHERE route_id = ?1 AND COALESCE(uid, player) = ?2",
+                params![route_id, player]
+            )?;
+        },
+        Mutation::AddRun { route_id, run } => {
+            transaction.execute(
+                "INSERT INTO runs (id, route_id, player, uid, time, splits, submitted, server) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                    ON CONFLICT (id) DO NOTHING",
+                params![run.id, route_id, run.entry.name, run.entry.uid, run.entry.time, write_json(&run.entry.splits)?, run.submitted, run.server]
+            )?;
+        },
+        Mutation::PutPlayer { player } => {
+            let data = serde_json::to_string(player)
+                .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+            transaction.execute(
+                "INSERT INTO players (uid, data) VALUES (?1, ?2)
+                    ON CONFLICT (uid) DO UPDATE SET data = ?2",
+                params![player.uid, data]
+            )?;
+            transaction.execute("UPDATE scores SET player = ?2 WHERE uid = ?1", params![player.uid, player.name])?;
+            transaction.execute("UPDATE runs SET player = ?2 WHERE uid = ?1", params![player.uid, player.name])?;
+        },
+        Mutation::FlagRun { run } => {
+            let data = serde_json::to_string(run)
+                .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+            transaction.execute(
+                "INSERT INTO flagged_runs (id, route_id, data) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (id) DO UPDATE SET route_id = ?2, data = ?3",
+                params![run.id, run.route_id, data]
+            )?;
+        },
+        Mutation::UnflagRun { run_id } => {
+            transaction.execute("DELETE FROM flagged_runs WHERE id = ?1", params![run_id])?;
+        },
+        Mutation::RemoveOrphanRoutes { map_id } => {
+            for table in ["scores", "runs", "flagged_runs"] {
+                transaction.execute(
+                    &format!("DELETE FROM {} WHERE route_id IN (SELECT id FROM routes WHERE map_id = ?1)", table),
+                    params![map_id]
+                )?;
+            }
+            transaction.execute("DELETE FROM routes WHERE map_id = ?1", params![map_id])?;
+        },
+        Mutation::RemoveOrphanScores { route_id } => {
+            transaction.execute("DELETE FROM scores WHERE route_id = ?1", params![route_id])?;
+            transaction.execute("DELETE FROM runs WHERE route_id = ?1", params![route_id])?;
+            transaction.execute("DELETE FROM flagged_runs WHERE route_id = ?1", params![route_id])?;
+        }
+    }
+    Ok(())
+}
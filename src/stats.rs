@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use warp::{hyper::StatusCode, Filter, Reply, Rejection};
+
+use crate::Store;
+
+
+/// Statistics of a route leaderboard and history.
+/// 
+#[derive(Serialize)]
+struct RouteStats {
+    map_id: String,
+    map_name: String,
+    route_id: String,
+    route_name: String,
+    /// Number of players on the leaderboard
+    players: usize,
+    /// Number of runs submitted on the route
+    runs: usize,
+    best_time: Option<f32>,
+    /// Median of players' best times
+    median_time: Option<f32>
+}
+
+/// Activity of an event on a given day.
+/// 
+#[derive(Serialize)]
+struct DayActivity {
+    /// UTC date, formatted as `YYYY-MM-DD`
+    date: String,
+    runs: usize,
+    players: usize
+}
+
+#[derive(Serialize)]
+struct EventStats {
+    event_id: String,
+    event_name: String,
+    /// Number of unique players across all routes of the event
+    players: usize,
+    runs: usize,
+    routes: Vec<RouteStats>,
+    /// Runs submitted per day (runs recorded before history was kept don't
+    /// have a submission time, and are not counted)
+    activity: Vec<DayActivity>
+}
+
+
+/// Median of a sorted list of times.
+/// 
+fn median(times: &[f32]) -> Option<f32> {
+    let count = times.len();
+    match count {
+        0 => None,
+        _ if count % 2 == 1 => Some(times[count / 2]),
+        _ => Some((times[count / 2 - 1] + times[count / 2]) / 2.0)
+    }
+}
+
+
+fn format_date(timestamp: i64) -> String {
+    match NaiveDateTime::from_timestamp_opt(timestamp, 0) {
+        Some(naive) => DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc).format("%Y-%m-%d").to_string(),
+        None => String::from("unknown")
+    }
+}
+
+
+/// Computes the statistics of an event, from the leaderboards and run history
+/// of all its routes.
+/// 
+async fn get_event_stats(
+    event_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let events = store.events_list.read();
+        let maps_list = store.maps_list.read();
+        let routes_list = store.routes_list.read();
+        let scores_list = store.scores_list.read();
+        let runs_list = store.runs_list.read();
+
+        let event = match events.iter().find(|e| e.id.as_deref() == Some(event_id.as_str())) {
+            Some(event) => event,
+            None => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Event not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        };
+
+        let mut players: HashSet<&str> = HashSet::new();
+        let mut routes: Vec<RouteStats> = Vec::new();
+        let mut days: BTreeMap<String, (usize, HashSet<&str>)> = BTreeMap::new();
+        for map in maps_list.get(&event_id).into_iter().flatten() {
+            let map_id = map.id.clone().unwrap_or_default();
+            for route in routes_list.get(&map_id).into_iter().flatten() {
+                let route_id = route.id.clone().unwrap_or_default();
+                let scores = scores_list.get(&route_id).map(|s| s.as_slice()).unwrap_or_default();
+                let runs = runs_list.get(&route_id).map(|r| r.as_slice()).unwrap_or_default();
+
+                players.extend(scores.iter().map(|e| e.player_id()));
+                for run in runs {
+                    if let Some(submitted) = run.submitted {
+                        let day = days.entry(format_date(submitted)).or_default();
+                        day.0 += 1;
+                        day.1.insert(run.entry.player_id());
+                    }
+                }
+
+                // Leaderboards are sorted by time
+                let times: Vec<f32> = scores.iter().map(|e| e.time).collect();
+                routes.push(RouteStats {
+                    map_id: map_id.clone(),
+                    map_name: map.map_name.clone(),
+                    route_id,
+                    route_name: route.name.clone(),
+                    players: scores.len(),
+                    runs: runs.len(),
+                    best_time: times.first().copied(),
+                    median_time: median(&times)
+                });
+            }
+        }
+
+        let stats = EventStats {
+            event_id: event_id.clone(),
+            event_name: event.name.clone(),
+            players: players.len(),
+            runs: routes.iter().map(|r| r.runs).sum(),
+            routes,
+            activity: days.into_iter()
+                .map(|(date, (runs, players))| DayActivity { date, runs, players: players.len() })
+                .collect()
+        };
+        Ok(warp::reply::with_status(
+            warp::reply::json(&stats),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns all statistics-associated routes:
+///     * one route to get the statistics of an event.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let store_filter = warp::any().map(move || store.clone());
+
+    warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("events"))
+        .and(warp::path::param())
+        .and(warp::path("stats"))
+        .and(warp::path::end())
+        .and(store_filter)
+        .and_then(get_event_stats)
+}
@@ -0,0 +1,172 @@
+use std::{sync::{mpsc, Arc}, thread, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::sync::watch;
+use warp::{Reply, Rejection};
+
+use crate::config::Config;
+use crate::journal::Mutation;
+use crate::persistence::JsonStorage;
+use crate::sqlite::SqliteStorage;
+use crate::{Store, log};
+
+
+/// A place where store state is persisted.
+/// 
+/// The store remains the source of truth while the API runs: backends restore
+/// it on startup, and are then notified of every mutation applied to it.
+/// 
+pub trait Storage: Send + Sync {
+    /// Fills the store with previously persisted state.
+    /// 
+    fn load(&self, store: &Store) -> Result<(), String>;
+
+    /// Queues a mutation that was just applied to the store for persistence;
+    /// this must be called while still holding the write lock(s) used to apply
+    /// it, so that recorded order matches store order.
+    /// 
+    fn record(&self, mutation: Mutation);
+
+    /// Returns a handle on the persistence of all mutations recorded so far,
+    /// which handlers wait for (once they've released store locks) before
+    /// acknowledging a change.
+    /// 
+    fn commit(&self) -> Commit;
+
+    /// Called periodically (and before exiting) to persist the whole state.
+    /// 
+    fn save(&self, store: &Store) -> Result<(), String>;
+}
+
+
+/// Completion of the persistence of queued items.
+/// 
+pub struct Commit {
+    seq: u64,
+    written: watch::Receiver<u64>
+}
+
+impl Commit {
+    /// Resolves once all items queued before the commit was taken have been
+    /// written (or failed being written, which is reported by the writer).
+    /// 
+    pub async fn wait(mut self) {
+        let seq = self.seq;
+        // Writer thread never stops, but a closed channel shouldn't block anyone
+        let _ = self.written.wait_for(|written| *written >= seq).await;
+    }
+}
+
+
+/// Replies once the mutations recorded while handling a request have been
+/// persisted; chained after mutating handlers, which can't wait themselves
+/// while holding store locks.
+/// 
+pub async fn wait_commit<T: Reply>(reply: T, store: Store) -> Result<T, Rejection> {
+    store.storage.commit().wait().await;
+    Ok(reply)
+}
+
+
+/// Hands items over to a background thread which persists them in batches,
+/// so that threads holding store locks never wait for the disk.
+/// 
+/// Items are numbered in the order they're queued, and written in that order.
+/// 
+pub struct WriteQueue<T> {
+    queue: Mutex<(u64, mpsc::Sender<(u64, T)>)>,
+    written: Arc<watch::Sender<u64>>
+}
+
+impl<T: Send + 'static> WriteQueue<T> {
+    /// Starts the writer thread, which calls `write` with all items queued
+    /// since its previous call.
+    /// 
+    pub fn start(mut write: impl FnMut(Vec<(u64, T)>) + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel::<(u64, T)>();
+        let written = Arc::new(watch::channel(0).0);
+        let thread_written = written.clone();
+
+        thread::spawn(move || {
+            while let Ok(item) = receiver.recv() {
+                let mut batch = vec![item];
+                batch.extend(receiver.try_iter());
+                let seq = batch.last().map(|(seq, _)| *seq).unwrap_or(0);
+                write(batch);
+                thread_written.send_replace(seq);
+            }
+        });
+
+        WriteQueue { queue: Mutex::new((0, sender)), written }
+    }
+
+    /// Queues an item, returning its number.
+    /// 
+    pub fn push(&self, item: T) -> u64 {
+        let mut queue = self.queue.lock();
+        queue.0 += 1;
+        let seq = queue.0;
+        // Writer thread never stops, so queue is always open
+        let _ = queue.1.send((seq, item));
+        seq
+    }
+
+    /// Number of the last queued item.
+    /// 
+    pub fn last_seq(&self) -> u64 {
+        self.queue.lock().0
+    }
+
+    /// Numbers next items after the given one (which is considered written).
+    /// 
+    pub fn reset(&self, seq: u64) {
+        let mut queue = self.queue.lock();
+        queue.0 = seq;
+        self.written.send_replace(seq);
+    }
+
+    pub fn commit(&self) -> Commit {
+        Commit { seq: self.last_seq(), written: self.written.subscribe() }
+    }
+}
+
+
+/// Builds the storage backend selected in configuration (`json` or `sqlite`).
+/// 
+pub fn from_config(config: &Config) -> Arc<dyn Storage> {
+    match config.storage.as_str() {
+        "sqlite" => {
+            log::info("Using SQLite storage.");
+            match SqliteStorage::open(&config.data_dir) {
+                Ok(storage) => Arc::new(storage),
+                Err(err) => {
+                    log::error(&format!("Failed opening SQLite database [{}].", err));
+                    std::process::exit(2);
+                }
+            }
+        },
+        _ => {
+            log::info("Using JSON files storage.");
+            Arc::new(JsonStorage::new(&config.data_dir))
+        }
+    }
+}
+
+
+/// Starts a thread that will save store state every few minutes (15 by
+/// default, see `Config::save_timer_minutes`).
+/// 
+pub fn start_save_cron(store: Store, interval_minutes: u64) {
+    log::info(&format!("Saving state every {} minute(s).", interval_minutes));
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(interval_minutes * 60));
+
+            // Previously saved state is still intact, we'll try again on next tick
+            if let Err(err) = store.storage.save(&store) {
+                log::error(&format!("Failed saving state [{}].", err));
+            }
+        }
+    });
+}
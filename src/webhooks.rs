@@ -0,0 +1,898 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use parking_lot::{Mutex, RwLock};
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use serde_json::json;
+use sha2::Sha256;
+use tokio::{sync::mpsc, time::Instant};
+use uuid::Uuid;
+use warp::{hyper::{client::HttpConnector, Body, Client, Request, StatusCode, Uri}, Filter, Reply, Rejection};
+
+use crate::{Store, keys::{self, Scope}, log, notifications::{Notification, NotificationKind}, persistence, route, storage::{Commit, WriteQueue}};
+
+const WEBHOOKS_FILE: &str = "webhooks.json";
+const DELIVERIES_FILE: &str = "webhook_deliveries.json";
+
+const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Number of attempts made to deliver a payload before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled after each failed attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Time a webhook endpoint is given to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of deliveries kept in each webhook log.
+const LOG_SIZE: usize = 100;
+
+/// Number of deliveries that can wait for an attempt; new deliveries fail
+/// while that many are waiting.
+const QUEUE_SIZE: usize = 1000;
+
+
+/// How payloads sent to a webhook are formatted.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// The notification, along with event, map and route names
+    #[default]
+    Json,
+    /// A Discord webhook message, holding an embed describing the record
+    Discord
+}
+
+
+/// A subscription to new records, as stored in the registry.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    /// Only records of this event are delivered (records of all events are
+    /// delivered if unset)
+    pub event_id: Option<String>,
+    pub format: WebhookFormat,
+    /// Secret payloads are signed with
+    secret: String,
+    pub created: i64
+}
+
+impl Webhook {
+    /// Webhook description returned by the API, which doesn't include its
+    /// secret.
+    /// 
+    fn describe(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "url": self.url,
+            "event_id": self.event_id,
+            "format": self.format,
+            "created": self.created
+        })
+    }
+
+    fn matches(&self, notification: &Notification) -> bool {
+        self.event_id.is_none() || self.event_id == notification.event_id
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhookRequest {
+    url: String,
+    event_id: Option<String>,
+    #[serde(default)]
+    format: WebhookFormat,
+    /// Signing secret shared with the receiver (generated if not provided)
+    secret: Option<String>
+}
+
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum DeliveryStatus {
+    /// Waiting for an attempt (or for a retry)
+    Pending,
+    Delivered,
+    /// All attempts failed, or the endpoint refused the payload
+    Failed
+}
+
+/// A payload sent (or being sent) to a webhook.
+/// 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct Delivery {
+    id: String,
+    route_id: String,
+    status: DeliveryStatus,
+    attempts: u32,
+    /// HTTP status of the last response
+    response_status: Option<u16>,
+    /// Why the last attempt failed
+    error: Option<String>,
+    created: i64,
+    updated: i64,
+    body: String
+}
+
+/// Recent deliveries of each webhook, newest first.
+type DeliveryLogs = HashMap<String, VecDeque<Delivery>>;
+
+/// A delivery attempt waiting in the queue.
+/// 
+struct Job {
+    webhook_id: String,
+    delivery_id: String,
+    body: String,
+    attempt: u32,
+    /// When the attempt can be made
+    due: Instant
+}
+
+
+/// Webhook subscriptions, persisted in the data directory, along with the
+/// recent deliveries of each webhook (which are persisted too, so that
+/// pending deliveries are resumed after a restart).
+/// 
+pub struct WebhookRegistry {
+    path: PathBuf,
+    webhooks: RwLock<Vec<Webhook>>,
+    deliveries: Arc<Mutex<DeliveryLogs>>,
+    /// Requests to write delivery logs, handled by a writer thread
+    saves: WriteQueue<()>,
+    queue: mpsc::Sender<Job>,
+    pending: Mutex<Option<mpsc::Receiver<Job>>>,
+    /// Delay before the first retry of a delivery
+    retry_delay: Duration
+}
+
+impl WebhookRegistry {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(WEBHOOKS_FILE);
+        let webhooks: Vec<Webhook> = read_file(&path)?;
+        if !webhooks.is_empty() {
+            log::info(&format!("Loaded {} webhook(s).", webhooks.len()));
+        }
+        let deliveries_path = data_dir.join(DELIVERIES_FILE);
+        let deliveries = Arc::new(Mutex::new(read_file(&deliveries_path)?));
+        let writer_deliveries = deliveries.clone();
+
+        let (queue, pending) = mpsc::channel(QUEUE_SIZE);
+        Ok(WebhookRegistry {
+            path,
+            webhooks: RwLock::new(webhooks),
+            deliveries,
+            saves: WriteQueue::start(move |_| write_deliveries(&writer_deliveries, &deliveries_path)),
+            queue,
+            pending: Mutex::new(Some(pending)),
+            retry_delay: RETRY_DELAY
+        })
+    }
+
+    fn save(&self, webhooks: &[Webhook]) -> Result<(), String> {
+        let data = serde_json::to_vec(webhooks)
+            .map_err(|err| format!("failed serializing webhooks [{}]", err))?;
+        persistence::replace_file(&self.path, &data)
+    }
+
+    /// Queues a write of delivery logs; the writer thread writes them once for
+    /// all writes queued while it was busy.
+    /// 
+    fn save_deliveries(&self) {
+        self.saves.push(());
+    }
+
+    /// Waits for queued delivery log writes.
+    /// 
+    pub fn commit(&self) -> Commit {
+        self.saves.commit()
+    }
+
+    /// Logs a new delivery to a webhook, and queues its first attempt; the
+    /// delivery fails right away if the queue is full.
+    /// 
+    fn enqueue(&self, webhook_id: &str, route_id: &str, body: String) {
+        let now = Utc::now().timestamp();
+        let mut delivery = Delivery {
+            id: Uuid::new_v4().to_string(),
+            route_id: route_id.to_string(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            error: None,
+            created: now,
+            updated: now,
+            body: body.clone()
+        };
+        let job = Job { webhook_id: webhook_id.to_string(), delivery_id: delivery.id.clone(), body, attempt: 1, due: Instant::now() };
+
+        // Log is locked while queueing, so that the first attempt can't be
+        // recorded before the delivery is logged; worker never stops, so
+        // queueing only fails if the queue is full
+        let mut deliveries = self.deliveries.lock();
+        if self.queue.try_send(job).is_err() {
+            log::warn(&format!("Webhook delivery queue is full, dropping delivery to webhook {}.", webhook_id));
+            delivery.status = DeliveryStatus::Failed;
+            delivery.error = Some(String::from("delivery queue is full"));
+        }
+        let entries = deliveries.entry(webhook_id.to_string()).or_default();
+        entries.push_front(delivery);
+        entries.truncate(LOG_SIZE);
+    }
+
+    /// Attempts of deliveries that were pending when the API last stopped.
+    /// 
+    fn pending_jobs(&self) -> Vec<Job> {
+        let deliveries = self.deliveries.lock();
+        deliveries.iter()
+            .flat_map(|(webhook_id, entries)| entries.iter()
+                .filter(|d| d.status == DeliveryStatus::Pending)
+                .map(|d| Job {
+                    webhook_id: webhook_id.clone(),
+                    delivery_id: d.id.clone(),
+                    body: d.body.clone(),
+                    attempt: d.attempts + 1,
+                    due: Instant::now()
+                }))
+            .collect()
+    }
+
+    /// Records the outcome of a delivery attempt.
+    /// 
+    fn update(&self, job: &Job, status: DeliveryStatus, response_status: Option<u16>, error: Option<String>) {
+        let mut deliveries = self.deliveries.lock();
+        let delivery = deliveries.get_mut(&job.webhook_id)
+            .and_then(|entries| entries.iter_mut().find(|d| d.id == job.delivery_id));
+        if let Some(delivery) = delivery {
+            delivery.status = status;
+            delivery.attempts = job.attempt;
+            delivery.response_status = response_status;
+            delivery.error = error;
+            delivery.updated = Utc::now().timestamp();
+        }
+    }
+}
+
+
+/// Writes delivery logs to disk; failing to do so isn't fatal, as only
+/// pending deliveries would be lost on restart.
+/// 
+fn write_deliveries(deliveries: &Mutex<DeliveryLogs>, path: &Path) {
+    // Only serializing is done while holding the logs
+    let data = serde_json::to_vec(&*deliveries.lock())
+        .map_err(|err| format!("failed serializing webhook deliveries [{}]", err));
+    if let Err(err) = data.and_then(|data| persistence::replace_file(path, &data)) {
+        log::error(&format!("Failed saving webhook deliveries [{}].", err));
+    }
+}
+
+
+/// Deserializes a JSON file of the data directory (which may not exist yet).
+/// 
+fn read_file<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(T::default())
+    };
+    let mut data = String::new();
+    file.read_to_string(&mut data)
+        .map_err(|err| format!("failed reading \"{}\" file [{}]", path.display(), err))?;
+    serde_json::from_str(&data)
+        .map_err(|err| format!("failed deserializing \"{}\" file [{}]", path.display(), err))
+}
+
+
+fn random_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+
+/// Hex-encoded HMAC-SHA256 (keyed with the webhook secret) of the timestamp
+/// and body, separated by a newline.
+/// 
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}\n", timestamp).as_bytes());
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+
+/// Names of the event, map and route a notification is about.
+/// 
+#[derive(Debug, Serialize, Default)]
+struct Names {
+    event_name: Option<String>,
+    map_name: Option<String>,
+    route_name: Option<String>
+}
+
+fn find_names(store: &Store, notification: &Notification) -> Names {
+    let (map_id, route) = match route::find_route(store, &notification.route_id) {
+        Some(found) => found,
+        None => return Names::default()
+    };
+    let event_name = notification.event_id.as_ref().and_then(|event_id| {
+        store.events_list.read().iter()
+            .find(|e| e.id.as_ref() == Some(event_id))
+            .map(|e| e.name.clone())
+    });
+    let map_name = store.maps_list.read().values().flatten()
+        .find(|m| m.id.as_deref() == Some(map_id.as_str()))
+        .map(|m| m.map_name.clone());
+    Names { event_name, map_name, route_name: Some(route.name) }
+}
+
+
+/// Builds the body of a new record payload.
+/// 
+fn format_payload(format: WebhookFormat, notification: &Notification, names: &Names) -> serde_json::Value {
+    let (entry, previous) = match &notification.kind {
+        NotificationKind::NewRecord { entry, previous } => (entry, previous),
+        _ => return json!(notification)
+    };
+    match format {
+        WebhookFormat::Json => {
+            let mut payload = json!(notification);
+            payload["event_name"] = json!(names.event_name);
+            payload["map_name"] = json!(names.map_name);
+            payload["route_name"] = json!(names.route_name);
+            payload
+        },
+        WebhookFormat::Discord => {
+            let route_name = names.route_name.as_deref().unwrap_or("unknown route");
+            let title = match &names.map_name {
+                Some(map_name) => format!("New record on {} ({})", route_name, map_name),
+                None => format!("New record on {}", route_name)
+            };
+            let description = match previous {
+                Some(previous) => format!(
+                    "**{}** set a new record of **{:.3}s**, beating {}'s {:.3}s by {:.3}s.",
+                    entry.name, entry.time, previous.name, previous.time, previous.time - entry.time
+                ),
+                None => format!("**{}** set the first time on this route: **{:.3}s**.", entry.name, entry.time)
+            };
+            let mut embed = json!({
+                "title": title,
+                "description": description,
+                "color": 0xF5A623,
+                "timestamp": Utc::now().to_rfc3339()
+            });
+            if let Some(event_name) = &names.event_name {
+                embed["footer"] = json!({"text": event_name});
+            }
+            json!({
+                "username": "Parkour",
+                "embeds": [embed]
+            })
+        }
+    }
+}
+
+
+/// Turns a new record into payloads for the webhooks it matches, and queues
+/// them for delivery.
+/// 
+/// This is called by the notifications dispatcher (so that no record is
+/// missed), which is free to lock the store.
+/// 
+pub fn enqueue(store: &Store, notification: &Notification) {
+    if !matches!(notification.kind, NotificationKind::NewRecord { .. }) {
+        return;
+    }
+    let webhooks: Vec<Webhook> = store.webhooks.webhooks.read().iter()
+        .filter(|w| w.matches(notification))
+        .cloned()
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+    let names = find_names(store, notification);
+    for webhook in webhooks {
+        let body = format_payload(webhook.format, notification, &names).to_string();
+        store.webhooks.enqueue(&webhook.id, &notification.route_id, body);
+    }
+    store.webhooks.save_deliveries();
+}
+
+
+/// Starts delivering queued payloads to webhooks, resuming deliveries that
+/// were pending when the API last stopped.
+/// 
+pub fn start_delivery(store: Store) {
+    let pending = match store.webhooks.pending.lock().take() {
+        Some(pending) => pending,
+        None => return
+    };
+    let jobs = store.webhooks.pending_jobs();
+    if !jobs.is_empty() {
+        log::info(&format!("Resuming {} pending webhook delivery(ies).", jobs.len()));
+    }
+    tokio::spawn(deliver_queue(store, pending, jobs));
+}
+
+
+/// Client reaching webhooks over HTTP or HTTPS (servers being authenticated
+/// with Mozilla's root certificates, so that no system ones are needed).
+/// 
+fn https_client() -> Client<HttpsConnector<HttpConnector>> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+
+/// Sends queued payloads one at a time; failed attempts are made again after
+/// a delay that doubles with each attempt.
+/// 
+/// Attempts waiting for their delay are kept by the worker; when too many of
+/// them are waiting, it stops taking new deliveries from the queue (which then
+/// fills up).
+/// 
+async fn deliver_queue(store: Store, mut queue: mpsc::Receiver<Job>, mut jobs: Vec<Job>) {
+    let client = https_client();
+    loop {
+        let now = Instant::now();
+        if let Some(index) = jobs.iter().position(|j| j.due <= now) {
+            let job = jobs.swap_remove(index);
+            if let Some(retry) = attempt(&store, &client, job).await {
+                jobs.push(retry);
+            }
+            continue;
+        }
+
+        let next_retry = jobs.iter().map(|j| j.due).min();
+        tokio::select! {
+            job = queue.recv(), if jobs.len() < QUEUE_SIZE => match job {
+                Some(job) => jobs.push(job),
+                None => return
+            },
+            _ = tokio::time::sleep_until(next_retry.unwrap_or(now)), if next_retry.is_some() => ()
+        }
+    }
+}
+
+
+/// Makes a delivery attempt, returning the next one if it failed and can be
+/// retried.
+/// 
+async fn attempt(store: &Store, client: &Client<HttpsConnector<HttpConnector>>, mut job: Job) -> Option<Job> {
+    let webhook = match store.webhooks.webhooks.read().iter().find(|w| w.id == job.webhook_id) {
+        Some(webhook) => webhook.clone(),
+        // Webhook was deleted in the meantime
+        None => return None
+    };
+
+    let (response_status, error, retry) = match send(client, &webhook, &job.body).await {
+        Ok(status) if status.is_success() => (Some(status.as_u16()), None, false),
+        // Endpoint refused the payload, sending it again won't help
+        Ok(status) if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS => {
+            (Some(status.as_u16()), Some(format!("endpoint answered {}", status)), false)
+        },
+        Ok(status) => (Some(status.as_u16()), Some(format!("endpoint answered {}", status)), true),
+        Err(err) => (None, Some(err), true)
+    };
+    let status = match (&error, retry) {
+        (None, _) => DeliveryStatus::Delivered,
+        (Some(_), true) if job.attempt < MAX_ATTEMPTS => DeliveryStatus::Pending,
+        (Some(_), _) => DeliveryStatus::Failed
+    };
+    if let Some(err) = &error {
+        log::warn(&format!("Failed delivering payload to webhook {} (attempt {}/{}) [{}].", webhook.id, job.attempt, MAX_ATTEMPTS, err));
+    }
+    store.webhooks.update(&job, status, response_status, error);
+    store.webhooks.save_deliveries();
+
+    if status != DeliveryStatus::Pending {
+        return None;
+    }
+    job.due = Instant::now() + store.webhooks.retry_delay * 2u32.pow(job.attempt - 1);
+    job.attempt += 1;
+    Some(job)
+}
+
+
+async fn send(client: &Client<HttpsConnector<HttpConnector>>, webhook: &Webhook, body: &str) -> Result<StatusCode, String> {
+    let timestamp = Utc::now().timestamp();
+    let request = Request::post(webhook.url.as_str())
+        .header("content-type", "application/json")
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, timestamp, body))
+        .body(Body::from(body.to_string()))
+        .map_err(|err| format!("invalid request [{}]", err))?;
+    match tokio::time::timeout(REQUEST_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) => Ok(response.status()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(String::from("request timed out"))
+    }
+}
+
+
+/// Returns the list of all webhooks.
+/// 
+async fn get_list(
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let webhooks: Vec<serde_json::Value> = store.webhooks.webhooks.read().iter().map(|w| w.describe()).collect();
+        Ok(warp::reply::json(&webhooks))
+}
+
+
+/// This middleware creates `WebhookRequest` payloads from POST request bodies.
+/// 
+pub fn post_json() -> impl Filter<Extract = (WebhookRequest,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+
+/// Subscribes a webhook to new records; its secret is only returned by this
+/// request.
+/// 
+async fn create_webhook(
+    request: WebhookRequest,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        match request.url.parse::<Uri>() {
+            Ok(uri) if matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some() => (),
+            _ => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Webhook URL must be an http:// or https:// URL."})),
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+        if let Some(event_id) = &request.event_id {
+            if !store.events_list.read().iter().any(|e| e.id.as_ref() == Some(event_id)) {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": "Event not found."})),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+        }
+
+        let secret = request.secret.unwrap_or_else(random_secret);
+        let webhook = Webhook {
+            id: Uuid::new_v4().to_string(),
+            url: request.url,
+            event_id: request.event_id,
+            format: request.format,
+            secret: secret.clone(),
+            created: Utc::now().timestamp()
+        };
+        let mut webhooks = store.webhooks.webhooks.write();
+        webhooks.push(webhook.clone());
+        if let Err(err) = store.webhooks.save(&webhooks) {
+            webhooks.pop();
+            log::error(&format!("Failed saving webhooks [{}].", err));
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Failed saving webhook."})),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+        log::info(&format!("Created webhook {}.", webhook.id));
+
+        let mut response = webhook.describe();
+        response["secret"] = json!(secret);
+        Ok(warp::reply::with_status(
+            warp::reply::json(&response),
+            StatusCode::CREATED,
+        ))
+}
+
+
+/// Deletes a webhook, along with its delivery log; queued deliveries are
+/// dropped.
+/// 
+async fn delete_webhook(
+    webhook_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        let mut webhooks = store.webhooks.webhooks.write();
+        let index = match webhooks.iter().position(|w| w.id == webhook_id) {
+            Some(index) => index,
+            None => return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Webhook not found."})),
+                StatusCode::NOT_FOUND,
+            ))
+        };
+
+        let webhook = webhooks.remove(index);
+        if let Err(err) = store.webhooks.save(&webhooks) {
+            webhooks.insert(index, webhook);
+            log::error(&format!("Failed saving webhooks [{}].", err));
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Failed saving webhook."})),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+        store.webhooks.deliveries.lock().remove(&webhook_id);
+        store.webhooks.save_deliveries();
+        log::info(&format!("Deleted webhook {}.", webhook_id));
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"message": "Webhook successfully deleted."})),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns the recent deliveries of a webhook, newest first.
+/// 
+async fn get_deliveries(
+    webhook_id: String,
+    store: Store
+    ) -> Result<impl Reply, Rejection> {
+        if !store.webhooks.webhooks.read().iter().any(|w| w.id == webhook_id) {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Webhook not found."})),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+        let deliveries: Vec<Delivery> = store.webhooks.deliveries.lock()
+            .get(&webhook_id)
+            .map(|entries| entries.iter().cloned().collect())
+            .unwrap_or_default();
+        Ok(warp::reply::with_status(
+            warp::reply::json(&deliveries),
+            StatusCode::OK,
+        ))
+}
+
+
+/// Returns all webhook-associated routes (which all require the `admin`
+/// scope):
+///     * one route to list all webhooks;
+///     * one route to create a webhook;
+///     * one route to delete a webhook;
+///     * one route to get the delivery log of a webhook.
+/// 
+pub fn get_routes(store: Store) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let admin_filter = keys::authorize(store.clone(), Scope::Admin);
+    let store_filter = warp::any().map(move || store.clone());
+
+    let get_all_webhooks = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("webhooks"))
+        .and(warp::path::end())
+        .and(admin_filter.clone())
+        .and(store_filter.clone())
+        .and_then(get_list);
+
+    let webhook_creation_route = warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path("webhooks"))
+        .and(warp::path::end())
+        .and(admin_filter.clone())
+        .and(post_json())
+        .and(store_filter.clone())
+        .and_then(create_webhook);
+
+    let webhook_deletion_route = warp::delete()
+        .and(warp::path("v1"))
+        .and(warp::path("webhooks"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(admin_filter.clone())
+        .and(store_filter.clone())
+        .and_then(delete_webhook);
+
+    let get_deliveries_route = warp::get()
+        .and(warp::path("v1"))
+        .and(warp::path("webhooks"))
+        .and(warp::path::param())
+        .and(warp::path("deliveries"))
+        .and(warp::path::end())
+        .and(admin_filter)
+        .and(store_filter)
+        .and_then(get_deliveries);
+
+    get_all_webhooks
+        .or(webhook_creation_route)
+        .or(webhook_deletion_route)
+        .or(get_deliveries_route)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use warp::{http::HeaderMap, hyper::body::Bytes};
+
+    use super::*;
+    use crate::{keys::KeyRegistry, persistence::JsonStorage, scores::ScoreEntry};
+
+    const SECRET: &str = "webhook secret";
+
+    /// A request received by the stand-in endpoint.
+    /// 
+    struct Received {
+        headers: HeaderMap,
+        body: String,
+        at: Instant
+    }
+
+    /// Starts a local endpoint standing in for a webhook receiver, which
+    /// answers requests with the given statuses (then with 200).
+    /// 
+    fn start_endpoint(statuses: Vec<u16>) -> (SocketAddr, mpsc::UnboundedReceiver<Received>) {
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let endpoint = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers: HeaderMap, body: Bytes| {
+                let body = String::from_utf8_lossy(&body).to_string();
+                let _ = sender.send(Received { headers, body, at: Instant::now() });
+                let status = statuses.lock().pop_front().unwrap_or(200);
+                warp::reply::with_status("", StatusCode::from_u16(status).unwrap())
+            });
+        let (address, server) = warp::serve(endpoint).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (address, receiver)
+    }
+
+    fn data_dir() -> PathBuf {
+        let data_dir = std::env::temp_dir().join(format!("parkour-api-webhooks-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        data_dir
+    }
+
+    /// Creates a store with a single webhook posting to the endpoint, whose
+    /// first retry waits 50ms.
+    /// 
+    fn store(data_dir: &Path, address: SocketAddr, format: WebhookFormat) -> Store {
+        let mut webhooks = WebhookRegistry::load(data_dir).unwrap();
+        webhooks.retry_delay = Duration::from_millis(50);
+        webhooks.webhooks.write().push(Webhook {
+            id: String::from("webhook"),
+            url: format!("http://{}/hook", address),
+            event_id: None,
+            format,
+            secret: String::from(SECRET),
+            created: 0
+        });
+        let keys = KeyRegistry::load(data_dir, Some(String::from("secret"))).unwrap();
+        Store::new(Arc::new(JsonStorage::new(data_dir)), Arc::new(keys), Arc::new(webhooks))
+    }
+
+    fn entry(name: &str, time: f32) -> ScoreEntry {
+        ScoreEntry { uid: None, name: name.to_string(), time, splits: None }
+    }
+
+    fn new_record(entry: ScoreEntry, previous: Option<ScoreEntry>) -> Notification {
+        Notification {
+            event_id: None,
+            route_id: String::from("route"),
+            kind: NotificationKind::NewRecord { entry, previous }
+        }
+    }
+
+    async fn next_request(requests: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+        tokio::time::timeout(Duration::from_secs(5), requests.recv()).await
+            .expect("endpoint wasn't reached")
+            .unwrap()
+    }
+
+    /// Waits for the latest delivery to stop being pending.
+    /// 
+    async fn settled_delivery(store: &Store) -> Delivery {
+        for _ in 0..100 {
+            let delivery = store.webhooks.deliveries.lock()["webhook"][0].clone();
+            if delivery.status != DeliveryStatus::Pending {
+                return delivery;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("delivery is still pending");
+    }
+
+    #[tokio::test]
+    async fn signs_payloads() {
+        let (address, mut requests) = start_endpoint(Vec::new());
+        let store = store(&data_dir(), address, WebhookFormat::Json);
+        start_delivery(store.clone());
+        enqueue(&store, &new_record(entry("alice", 10.5), None));
+
+        let request = next_request(&mut requests).await;
+        let timestamp = request.headers[TIMESTAMP_HEADER].to_str().unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(format!("{}\n{}", timestamp, request.body).as_bytes());
+        assert_eq!(request.headers[SIGNATURE_HEADER].to_str().unwrap(), hex::encode(mac.finalize().into_bytes()));
+        assert_eq!(request.headers["content-type"], "application/json");
+
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["type"], "new_record");
+        assert_eq!(body["entry"]["name"], "alice");
+    }
+
+    #[tokio::test]
+    async fn retries_failed_attempts_with_backoff() {
+        let (address, mut requests) = start_endpoint(vec![500, 429]);
+        let data_dir = data_dir();
+        let store = store(&data_dir, address, WebhookFormat::Json);
+        start_delivery(store.clone());
+        enqueue(&store, &new_record(entry("alice", 10.5), None));
+
+        let first = next_request(&mut requests).await;
+        let second = next_request(&mut requests).await;
+        let third = next_request(&mut requests).await;
+        assert!(second.at - first.at >= Duration::from_millis(50));
+        assert!(third.at - second.at >= Duration::from_millis(100));
+        assert_eq!(first.body, third.body);
+
+        let delivery = settled_delivery(&store).await;
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(delivery.response_status, Some(200));
+
+        // Delivery log is kept across restarts
+        store.webhooks.commit().wait().await;
+        let reloaded = WebhookRegistry::load(&data_dir).unwrap();
+        assert_eq!(reloaded.deliveries.lock()["webhook"][0].status, DeliveryStatus::Delivered);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_refused_payloads() {
+        let (address, mut requests) = start_endpoint(vec![400]);
+        let store = store(&data_dir(), address, WebhookFormat::Json);
+        start_delivery(store.clone());
+        enqueue(&store, &new_record(entry("alice", 10.5), None));
+
+        next_request(&mut requests).await;
+        let delivery = settled_delivery(&store).await;
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 1);
+        assert!(tokio::time::timeout(Duration::from_millis(300), requests.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn resumes_pending_deliveries() {
+        let (address, mut requests) = start_endpoint(Vec::new());
+        let data_dir = data_dir();
+        // Delivery is queued, but the API stops before attempting it
+        let stopped = store(&data_dir, address, WebhookFormat::Json);
+        enqueue(&stopped, &new_record(entry("alice", 10.5), None));
+        stopped.webhooks.commit().wait().await;
+
+        let store = store(&data_dir, address, WebhookFormat::Json);
+        start_delivery(store.clone());
+        next_request(&mut requests).await;
+        assert_eq!(settled_delivery(&store).await.status, DeliveryStatus::Delivered);
+    }
+
+    #[tokio::test]
+    async fn formats_discord_messages() {
+        let (address, mut requests) = start_endpoint(Vec::new());
+        let store = store(&data_dir(), address, WebhookFormat::Discord);
+        start_delivery(store.clone());
+        enqueue(&store, &new_record(entry("alice", 10.5), Some(entry("bob", 12.0))));
+
+        let request = next_request(&mut requests).await;
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["username"], "Parkour");
+        let embeds = body["embeds"].as_array().unwrap();
+        assert_eq!(embeds.len(), 1);
+        // Route isn't in the store, so its name is unknown
+        assert_eq!(embeds[0]["title"], "New record on unknown route");
+        assert_eq!(embeds[0]["description"], "**alice** set a new record of **10.500s**, beating bob's 12.000s by 1.500s.");
+        assert_eq!(embeds[0]["color"], 0xF5A623);
+        assert!(embeds[0]["timestamp"].is_string());
+        assert!(embeds[0].get("footer").is_none());
+    }
+}